@@ -24,7 +24,14 @@ use crate::myarray::*;
 use crate::shogi::*;
 
 /// bitboard テーブルたちを初期化する。**プログラム起動直後に必ずこれを呼ぶこと**。
-pub fn init() {
+///
+/// この呼び出しで初期化を完了させた場合は `true`、既に初期化済みだった場合は `false` を返す。
+/// 何度呼んでもよく、複数スレッドから同時に呼んでもよい。いずれの場合も、戻ったときには初期化が完了している。
+pub fn init() -> bool {
+    if INIT_DONE.get().is_some() {
+        return false;
+    }
+
     // これらは他のテーブル初期化時によく使われるので最初に初期化する。
     // これら自身は何にも依存せず初期化できる。
     BB_COL.get_or_init(init_col);
@@ -53,6 +60,9 @@ pub fn init() {
     BB_KNIGHT_EFFECT.get_or_init(init_knight_effect);
 
     BB_AROUND25.get_or_init(init_around25);
+    BB_BETWEEN.get_or_init(init_between);
+
+    INIT_DONE.set(()).is_ok()
 }
 
 #[cfg(any(debug_assertions, test))]
@@ -87,6 +97,10 @@ type BbGoldEffect = MyArray2<Bitboard, Square, Side, 81, 2>;
 type BbSilverEffect = MyArray2<Bitboard, Square, Side, 81, 2>;
 type BbKnightEffect = MyArray2<Bitboard, Square, Side, 81, 2>;
 type BbAround25 = MyArray1<Bitboard, Square, 81>;
+type BbBetween = MyArray2<Bitboard, Square, Square, 81, 81>;
+
+/// `init()` による初期化が完了したかどうか。
+static INIT_DONE: OnceCell<()> = OnceCell::new();
 
 static BB_COL: OnceCell<BbCol> = OnceCell::new();
 static BB_ROW: OnceCell<BbRow> = OnceCell::new();
@@ -104,6 +118,7 @@ static BB_GOLD_EFFECT: OnceCell<BbGoldEffect> = OnceCell::new();
 static BB_SILVER_EFFECT: OnceCell<BbSilverEffect> = OnceCell::new();
 static BB_KNIGHT_EFFECT: OnceCell<BbKnightEffect> = OnceCell::new();
 static BB_AROUND25: OnceCell<BbAround25> = OnceCell::new();
+static BB_BETWEEN: OnceCell<BbBetween> = OnceCell::new();
 
 /// 与えられた筋を表す bitboard を返す。
 pub fn col(col: Col) -> Bitboard {
@@ -129,12 +144,31 @@ pub fn forward_rows(side: Side, row: Row) -> Bitboard {
     bb[side][row]
 }
 
+/// `side` から見て `row` より自陣側の段たちを表す bitboard を返す。
+///
+/// `forward_rows(side, row)`, `row(row)`, `backward_rows(side, row)` は盤面を分割する。
+pub fn backward_rows(side: Side, row: Row) -> Bitboard {
+    (forward_rows(side, row) | self::row(row)).andnot(Bitboard::all())
+}
+
 /// `side` から見た敵陣を表す bitboard を返す。
 pub fn promotion_zone(side: Side) -> Bitboard {
     let bb = once_cell_get(&BB_PROMOTION_ZONE, init_promotion_zone);
     bb[side]
 }
 
+/// `side` から見た自陣を表す bitboard を返す。
+pub fn own_camp(side: Side) -> Bitboard {
+    promotion_zone(side.inv())
+}
+
+/// 中段 (4-6 段目) を表す bitboard を返す。
+///
+/// `promotion_zone(side)`, `middle_ranks()`, `own_camp(side)` は盤面を分割する。
+pub fn middle_ranks() -> Bitboard {
+    row(ROW_4) | row(ROW_5) | row(ROW_6)
+}
+
 /// `side` 側が `sq` に置いた歩の利きを返す。
 pub fn pawn_effect(side: Side, sq: Square) -> Bitboard {
     let bb = once_cell_get(&BB_PAWN_EFFECT, init_pawn_effect);
@@ -147,6 +181,16 @@ pub fn lance_step_effect(side: Side, sq: Square) -> Bitboard {
     bb[sq][side]
 }
 
+/// `side` 側の歩が `sq` に利きを持つようなマスを返す。
+pub fn pawn_attackers_to(side: Side, sq: Square) -> Bitboard {
+    pawn_effect(side.inv(), sq)
+}
+
+/// 盤面 `occ` において `side` 側の香が `sq` に利きを持つようなマスたちを返す。
+pub fn lance_attackers_to(side: Side, sq: Square, occ: Bitboard) -> Bitboard {
+    lance_effect(side.inv(), sq, occ)
+}
+
 /// 盤面 `occ` において `side` 側が `sq` に置いた香の利きを返す。
 pub fn lance_effect(side: Side, sq: Square, occ: Bitboard) -> Bitboard {
     if side == HUM {
@@ -158,53 +202,76 @@ pub fn lance_effect(side: Side, sq: Square, occ: Bitboard) -> Bitboard {
 
 /// 盤面 `occ` において HUM が `sq` に置いた香の利きを返す。
 fn lance_effect_hum(sq: Square, occ: Bitboard) -> Bitboard {
-    fn effect(step_eff: u64, occ: u64) -> u64 {
-        // step effect 上にある駒のみが利きに影響する。
-        let mut mask = step_eff & occ;
-
-        // HUM の香の利きは盤上の駒より上のマスには届かない。
-        mask |= mask >> 1;
-        mask |= mask >> 2;
-        mask |= mask >> 4;
-        mask >>= 1;
-
-        step_eff & !mask
-    }
-
     let step_eff = lance_step_effect(HUM, sq);
 
     if Bitboard::square_is_part0(sq) {
         let step_eff_lo = step_eff.part0();
         let occ_lo = occ.part0();
-        Bitboard::from_parts(effect(step_eff_lo, occ_lo), 0)
+        Bitboard::from_parts(lance_effect_hum_part(step_eff_lo, occ_lo), 0)
     } else {
         let step_eff_hi = step_eff.part1();
         let occ_hi = occ.part1();
-        Bitboard::from_parts(0, effect(step_eff_hi, occ_hi))
+        Bitboard::from_parts(0, lance_effect_hum_part(step_eff_hi, occ_hi))
     }
 }
 
 /// 盤面 `occ` において COM が `sq` に置いた香の利きを返す。
 fn lance_effect_com(sq: Square, occ: Bitboard) -> Bitboard {
-    fn effect(step_eff: u64, occ: u64) -> u64 {
-        // step effect 上にある駒のみが利きに影響する。
-        let mask = step_eff & occ;
-
-        // (x ^ (x-1)) は最下位の 1 以下のビットを 1 に、他を 0 にする。
-        // 1 のビットがなければ全ビットが 1 になる。
-        (mask ^ (mask.wrapping_sub(1))) & step_eff
-    }
-
     let step_eff = lance_step_effect(COM, sq);
 
     if Bitboard::square_is_part0(sq) {
         let step_eff_lo = step_eff.part0();
         let occ_lo = occ.part0();
-        Bitboard::from_parts(effect(step_eff_lo, occ_lo), 0)
+        Bitboard::from_parts(lance_effect_com_part(step_eff_lo, occ_lo), 0)
     } else {
         let step_eff_hi = step_eff.part1();
         let occ_hi = occ.part1();
-        Bitboard::from_parts(0, effect(step_eff_hi, occ_hi))
+        Bitboard::from_parts(0, lance_effect_com_part(step_eff_hi, occ_hi))
+    }
+}
+
+/// HUM の香の利きを 64bit の片側 part 上で求める。
+fn lance_effect_hum_part(step_eff: u64, occ: u64) -> u64 {
+    // step effect 上にある駒のみが利きに影響する。
+    let mut mask = step_eff & occ;
+
+    // HUM の香の利きは盤上の駒より上のマスには届かない。
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask >>= 1;
+
+    step_eff & !mask
+}
+
+/// COM の香の利きを 64bit の片側 part 上で求める。
+fn lance_effect_com_part(step_eff: u64, occ: u64) -> u64 {
+    // step effect 上にある駒のみが利きに影響する。
+    let mask = step_eff & occ;
+
+    // (x ^ (x-1)) は最下位の 1 以下のビットを 1 に、他を 0 にする。
+    // 1 のビットがなければ全ビットが 1 になる。
+    (mask ^ (mask.wrapping_sub(1))) & step_eff
+}
+
+/// 盤面 `occ` において `sq` から上下両方向に伸びる利き(飛車の縦利き)を返す。
+///
+/// `lance_effect(HUM, sq, occ) | lance_effect(COM, sq, occ)` と等しいが、
+/// `Bitboard::square_is_part0()` の判定は 1 回で済む。
+pub fn file_effect(sq: Square, occ: Bitboard) -> Bitboard {
+    let step_eff_hum = lance_step_effect(HUM, sq);
+    let step_eff_com = lance_step_effect(COM, sq);
+
+    if Bitboard::square_is_part0(sq) {
+        let occ_lo = occ.part0();
+        let eff = lance_effect_hum_part(step_eff_hum.part0(), occ_lo)
+            | lance_effect_com_part(step_eff_com.part0(), occ_lo);
+        Bitboard::from_parts(eff, 0)
+    } else {
+        let occ_hi = occ.part1();
+        let eff = lance_effect_hum_part(step_eff_hum.part1(), occ_hi)
+            | lance_effect_com_part(step_eff_com.part1(), occ_hi);
+        Bitboard::from_parts(0, eff)
     }
 }
 
@@ -216,10 +283,7 @@ pub fn rook_effect(sq: Square, occ: Bitboard) -> Bitboard {
 /// 盤面 `occ` において `sq` に置いた飛車の縦利きを返す。
 fn rook_col_effect(sq: Square, occ: Bitboard) -> Bitboard {
     // HUM の香の利きと COM の香の利きを合成すればよい。
-    // ベタ書きするのに比べ Bitboard::square_is_part0() の判定が 1 回増えるが、
-    // これくらいは最適化されてほしい。
-
-    lance_effect_hum(sq, occ) | lance_effect_com(sq, occ)
+    file_effect(sq, occ)
 }
 
 /// 盤面 `occ` において `sq` に置いた飛車の横利きを返す。
@@ -321,6 +385,11 @@ pub fn knight_effect(side: Side, sq: Square) -> Bitboard {
     bb[sq][side]
 }
 
+/// `side` 側の桂が `sq` に利きを持つようなマスたち(`sq` に王手をかけられる桂の位置)を返す。
+pub fn knight_attackers_to(side: Side, sq: Square) -> Bitboard {
+    knight_effect(side.inv(), sq)
+}
+
 /// 盤面 `occ` において `sq` に置いた龍の利きを返す。
 pub fn dragon_effect(sq: Square, occ: Bitboard) -> Bitboard {
     rook_effect(sq, occ) | king_effect(sq)
@@ -374,6 +443,29 @@ pub fn effect(pc: Piece, sq: Square, occ: Bitboard) -> Bitboard {
     }
 }
 
+/// 盤面 `occ` において `side` 側の駒種 `pk` を `sq` に置いたときの利きを返す。
+/// `pk` は実際の駒種でなければならない。
+///
+/// `Piece` を構築せずに済むので、駒打ちの利きの見積もりなどに使える。
+pub fn piece_kind_effect(side: Side, pk: PieceKind, sq: Square, occ: Bitboard) -> Bitboard {
+    debug_assert!(pk.is_piece());
+
+    match pk {
+        PAWN => pawn_effect(side, sq),
+        LANCE => lance_effect(side, sq, occ),
+        KNIGHT => knight_effect(side, sq),
+        SILVER => silver_effect(side, sq),
+        GOLD | PRO_PAWN | PRO_LANCE | PRO_KNIGHT | PRO_SILVER => gold_effect(side, sq),
+        BISHOP => bishop_effect(sq, occ),
+        ROOK => rook_effect(sq, occ),
+        HORSE => horse_effect(sq, occ),
+        DRAGON => dragon_effect(sq, occ),
+        KING => king_effect(sq),
+
+        _ => unreachable!(),
+    }
+}
+
 /// 駒 `pc` を `sq` に置いたときの近接利きを返す。
 /// `pc` は実際の駒でなければならない。
 pub fn effect_melee(pc: Piece, sq: Square) -> Bitboard {
@@ -483,6 +575,34 @@ pub fn around25(sq: Square) -> Bitboard {
     bb[sq]
 }
 
+/// `sq1` と `sq2` の間(両端を含まない)のマスが 1 である bitboard を返す。
+/// 2 つのマスが縦、横、斜めのいずれかに並んでいない場合、空の bitboard を返す。
+pub fn between(sq1: Square, sq2: Square) -> Bitboard {
+    let bb = once_cell_get(&BB_BETWEEN, init_between);
+    bb[sq1][sq2]
+}
+
+/// `sq` からチェス盤距離 `n` 以内のマスが 1 である bitboard を返す。`sq` 自身も含む。
+/// 盤面外にはみ出さない場合、横幅 `2n+1`, 縦幅 `2n+1` のマスとなる。
+///
+/// テーブルを引かずにその場で計算する。`n == 2` なら `around25()` の方が速い。
+pub fn around_n(sq: Square, n: u8) -> Bitboard {
+    // 距離 8 を超えると盤面全体になる。
+    let n = i32::from(n.min(8));
+
+    let mut bb = Bitboard::zero();
+
+    let cols = Col::iter_range(sq.col() - n, sq.col() + n).filter(|&col| col.is_on_board());
+    for col in cols {
+        let rows = Row::iter_range(sq.row() - n, sq.row() + n).filter(|&row| row.is_on_board());
+        for row in rows {
+            bb |= Bitboard::from(Square::from_col_row(col, row));
+        }
+    }
+
+    bb
+}
+
 #[allow(clippy::erasing_op)]
 #[allow(clippy::identity_op)]
 fn init_col() -> BbCol {
@@ -517,9 +637,7 @@ fn init_row() -> BbRow {
 }
 
 fn init_square() -> BbSquare {
-    let mut bb_square = BbSquare::default();
-
-    for sq in Square::iter() {
+    BbSquare::from_fn(|sq| {
         let col = sq.col();
         let row = sq.row();
 
@@ -532,10 +650,8 @@ fn init_square() -> BbSquare {
             )
         };
 
-        bb_square[sq] = Bitboard::from_parts(lo, hi);
-    }
-
-    bb_square
+        Bitboard::from_parts(lo, hi)
+    })
 }
 
 fn init_forward_rows() -> BbForwardRows {
@@ -816,18 +932,34 @@ fn init_around25() -> BbAround25 {
     let mut bb_around25 = BbAround25::default();
 
     for sq in Square::iter() {
-        bb_around25[sq] = Bitboard::zero();
+        bb_around25[sq] = around_n(sq, 2);
+    }
+
+    bb_around25
+}
+
+fn init_between() -> BbBetween {
+    let mut bb_between = BbBetween::default();
 
-        let cols = Col::iter_range(sq.col() - 2, sq.col() + 2).filter(|&col| col.is_on_board());
-        for col in cols {
-            let rows = Row::iter_range(sq.row() - 2, sq.row() + 2).filter(|&row| row.is_on_board());
-            for row in rows {
-                bb_around25[sq] |= Bitboard::from(Square::from_col_row(col, row));
+    for sq1 in Square::iter() {
+        for sq2 in Square::iter() {
+            // 2 つのマスが並んでいなければ空。
+            let dirs = DirectionSet::from_squares(sq1, sq2);
+            if dirs.is_empty() {
+                continue;
+            }
+
+            // sq1 から sq2 の方向へ sq2 の手前まで進む。
+            let delta = dirs.get_least().to_sqww_delta();
+            let mut sq_ww = SquareWithWall::from(sq1) + delta;
+            while Square::from(sq_ww) != sq2 {
+                bb_between[sq1][sq2] |= Bitboard::from(Square::from(sq_ww));
+                sq_ww += delta;
             }
         }
     }
 
-    bb_around25
+    bb_between
 }
 
 #[cfg(test)]
@@ -888,6 +1020,62 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_file_effect() {
+        let occs = [
+            Bitboard::zero(),
+            bb_from_sqs([SQ_77, SQ_74, SQ_71]),
+            bb_from_sqs([SQ_82, SQ_88, SQ_12, SQ_19]),
+            bb_from_sqs([SQ_13, SQ_16, SQ_93, SQ_96, SQ_53, SQ_54, SQ_56]),
+        ];
+
+        for occ in occs {
+            for sq in Square::iter() {
+                assert_eq!(
+                    file_effect(sq, occ),
+                    lance_effect(HUM, sq, occ) | lance_effect(COM, sq, occ)
+                );
+            }
+        }
+
+        let occ = bb_from_sqs([SQ_82, SQ_88]);
+        assert_eq!(
+            file_effect(SQ_85, occ),
+            bb_from_sqs([SQ_84, SQ_83, SQ_82, SQ_86, SQ_87, SQ_88])
+        );
+    }
+
+    #[test]
+    fn test_attackers_to() {
+        for side in [HUM, COM] {
+            for k in Square::iter() {
+                // 返されたマスにある駒は k に利きを持ち、それ以外のマスにある駒は持たない。
+                for sq in Square::iter() {
+                    assert_eq!(
+                        knight_attackers_to(side, k).test_square(sq),
+                        knight_effect(side, sq).test_square(k)
+                    );
+                    assert_eq!(
+                        pawn_attackers_to(side, k).test_square(sq),
+                        pawn_effect(side, sq).test_square(k)
+                    );
+                }
+            }
+        }
+
+        // ５九の HUM 玉に王手をかけられる COM の桂の位置は４七と６七。
+        assert_eq!(knight_attackers_to(COM, SQ_59), bb_from_sqs([SQ_47, SQ_67]));
+        assert!(knight_effect(COM, SQ_47).test_square(SQ_59));
+        assert_eq!(pawn_attackers_to(COM, SQ_59), bb_from_sqs([SQ_58]));
+
+        // ５九の HUM 玉に王手をかけられる COM の香の位置は５八から遮られるまで。
+        let occ = bb_from_sqs([SQ_59, SQ_55]);
+        assert_eq!(
+            lance_attackers_to(COM, SQ_59, occ),
+            bb_from_sqs([SQ_58, SQ_57, SQ_56, SQ_55])
+        );
+    }
+
     #[test]
     fn test_rook_effect() {
         let occ = bb_from_sqs([SQ_25, SQ_61, SQ_85, SQ_67]);
@@ -1054,4 +1242,111 @@ mod tests {
             ])
         );
     }
+
+    #[test]
+    fn test_around_n() {
+        for sq in Square::iter() {
+            assert_eq!(around_n(sq, 0), Bitboard::from(sq));
+            assert_eq!(around_n(sq, 1), king_effect(sq) | Bitboard::from(sq));
+            assert_eq!(around_n(sq, 2), around25(sq));
+            assert_eq!(around_n(sq, 8), Bitboard::all());
+            assert_eq!(around_n(sq, u8::MAX), Bitboard::all());
+
+            for n in 0..=8 {
+                assert_eq!(
+                    around_n(sq, n),
+                    bb_from_sqs(Square::iter().filter(|&other| sq.distance(other) <= n))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_between() {
+        assert_eq!(between(SQ_11, SQ_15), bb_from_sqs([SQ_12, SQ_13, SQ_14]));
+        assert_eq!(between(SQ_15, SQ_11), bb_from_sqs([SQ_12, SQ_13, SQ_14]));
+        assert_eq!(between(SQ_73, SQ_43), bb_from_sqs([SQ_53, SQ_63]));
+        assert_eq!(between(SQ_99, SQ_66), bb_from_sqs([SQ_77, SQ_88]));
+        assert_eq!(
+            between(SQ_19, SQ_91),
+            bb_from_sqs(Square::iter().filter(|&sq| {
+                sq != SQ_19 && sq != SQ_91 && sq.col() - COL_1 == ROW_9 - sq.row()
+            }))
+        );
+
+        // 隣接するマス、同じマス、並んでいないマス。
+        assert!(between(SQ_55, SQ_56).is_zero());
+        assert!(between(SQ_55, SQ_55).is_zero());
+        assert!(between(SQ_55, SQ_63).is_zero());
+        assert!(between(SQ_11, SQ_23).is_zero());
+    }
+
+    #[test]
+    fn test_backward_rows() {
+        assert_eq!(backward_rows(HUM, ROW_7), row(ROW_8) | row(ROW_9));
+        assert_eq!(backward_rows(COM, ROW_3), row(ROW_1) | row(ROW_2));
+        assert!(backward_rows(HUM, ROW_9).is_zero());
+        assert!(backward_rows(COM, ROW_1).is_zero());
+
+        // 前方の段、その段、後方の段で盤面を分割する。
+        for side in Side::iter() {
+            for r in Row::iter() {
+                let fwd = forward_rows(side, r);
+                let bwd = backward_rows(side, r);
+                assert!(!fwd.test(bwd) && !fwd.test(row(r)) && !bwd.test(row(r)));
+                assert_eq!(fwd | row(r) | bwd, Bitboard::all());
+            }
+        }
+    }
+
+    #[test]
+    fn test_own_camp_middle_ranks() {
+        init();
+
+        assert_eq!(own_camp(HUM), row(ROW_7) | row(ROW_8) | row(ROW_9));
+        assert_eq!(own_camp(COM), row(ROW_1) | row(ROW_2) | row(ROW_3));
+
+        // 敵陣、中段、自陣で盤面を分割する。
+        for side in Side::iter() {
+            let enemy = promotion_zone(side);
+            let middle = middle_ranks();
+            let own = own_camp(side);
+            assert!(!enemy.test(middle) && !enemy.test(own) && !middle.test(own));
+            assert_eq!(enemy | middle | own, Bitboard::all());
+        }
+    }
+
+    #[test]
+    fn test_init_idempotent() {
+        init();
+
+        // 2 回目以降の呼び出しは何もせず false を返す。
+        assert!(!init());
+        assert!(!init());
+
+        // テーブルは有効なまま。
+        assert_eq!(square(SQ_55).count_ones(), 1);
+        assert_eq!(own_camp(HUM), row(ROW_7) | row(ROW_8) | row(ROW_9));
+    }
+
+    #[test]
+    fn test_piece_kind_effect() {
+        let occs = [
+            Bitboard::zero(),
+            bb_from_sqs([SQ_22, SQ_55, SQ_58, SQ_73, SQ_86]),
+        ];
+
+        for occ in occs {
+            for side in [HUM, COM] {
+                for pk in PieceKind::iter_piece() {
+                    for sq in Square::iter() {
+                        assert_eq!(
+                            piece_kind_effect(side, pk, sq, occ),
+                            effect(Piece::new(side, pk), sq, occ)
+                        );
+                    }
+                }
+            }
+        }
+    }
 }
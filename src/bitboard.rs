@@ -127,6 +127,24 @@ impl Bitboard {
         self.part0().count_ones() + self.part1().count_ones()
     }
 
+    /// 筋ごとの 1 のビットの個数を返す。添字 0 が `COL_1` に対応する。
+    pub fn col_counts(self) -> [u8; 9] {
+        let mut counts = [0; 9];
+        for (count, col) in counts.iter_mut().zip(Col::iter()) {
+            *count = (self & bbs::col(col)).count_ones() as u8;
+        }
+        counts
+    }
+
+    /// 段ごとの 1 のビットの個数を返す。添字 0 が `ROW_1` に対応する。
+    pub fn row_counts(self) -> [u8; 9] {
+        let mut counts = [0; 9];
+        for (count, row) in counts.iter_mut().zip(Row::iter()) {
+            *count = (self & bbs::row(row)).count_ones() as u8;
+        }
+        counts
+    }
+
     /// 最下位の 1 に対応するマスを返す。self は 0 であってはならない。
     pub fn get_least_square(self) -> Square {
         debug_assert!(!self.is_zero());
@@ -171,6 +189,30 @@ impl Bitboard {
         std::iter::from_fn(move || (!bb.is_zero()).then(|| bb.pop_least_square()))
     }
 
+    /// 1 が立っているマスの部分集合(空集合と自身を含む)を全て列挙する。
+    ///
+    /// 各 64bit 部分を carry-rippler で列挙し、下位 64bit を下の桁として組み合わせる。
+    /// 遠隔利きのテーブル作成や、全ての駒配置に対するテストなどに使う。
+    pub fn subsets(self) -> impl Iterator<Item = Self> {
+        let (mask0, mask1) = (self.part0(), self.part1());
+
+        let mut next = Some((0_u64, 0_u64));
+        std::iter::from_fn(move || {
+            let (sub0, sub1) = next?;
+
+            let next0 = sub0.wrapping_sub(mask0) & mask0;
+            next = if next0 != 0 {
+                Some((next0, sub1))
+            } else {
+                // 下位 64bit が一周したら上位 64bit を進める。
+                let next1 = sub1.wrapping_sub(mask1) & mask1;
+                (next1 != 0).then_some((0, next1))
+            };
+
+            Some(Self::from_parts(sub0, sub1))
+        })
+    }
+
     /// 1 が立っている全てのマスについて `f` を呼ぶ。
     ///
     /// これは `pop_least_square()` を呼ぶループより速い。
@@ -681,6 +723,17 @@ mod tests {
         assert_equal(bb.squares(), sqs);
     }
 
+    #[test]
+    fn test_col_row_counts() {
+        let bb = bb_from_sqs([SQ_11, SQ_15, SQ_39, SQ_55, SQ_79, SQ_81, SQ_94, SQ_99]);
+
+        assert_eq!(bb.col_counts(), [2, 0, 1, 0, 1, 0, 1, 1, 2]);
+        assert_eq!(bb.row_counts(), [2, 0, 0, 1, 2, 0, 0, 0, 3]);
+
+        assert_eq!(Bitboard::zero().col_counts(), [0; 9]);
+        assert_eq!(Bitboard::all().row_counts(), [9; 9]);
+    }
+
     #[test]
     fn test_for_each_square() {
         let sqs_orig = [SQ_11, SQ_39, SQ_79, SQ_81, SQ_94, SQ_99];
@@ -763,4 +816,24 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_subsets() {
+        assert_eq!(
+            Bitboard::zero().subsets().collect::<Vec<_>>(),
+            [Bitboard::zero()]
+        );
+
+        // 両方の 64bit 部分にまたがるマスク。
+        let mask = bb_from_sqs([SQ_11, SQ_55, SQ_79, SQ_81, SQ_99]);
+        let subsets: Vec<_> = mask.subsets().collect();
+
+        assert_eq!(subsets.len(), 1 << mask.count_ones());
+        assert!(subsets.iter().all(|&sub| mask.andnot(sub).is_zero()));
+        for (i, &sub1) in subsets.iter().enumerate() {
+            assert!(subsets[i + 1..].iter().all(|&sub2| sub1 != sub2));
+        }
+        assert_eq!(subsets[0], Bitboard::zero());
+        assert_eq!(*subsets.last().unwrap(), mask);
+    }
 }
@@ -261,6 +261,117 @@ impl BookState {
 
         None
     }
+
+    /// 現在の局面で `next_move()` を繰り返し呼んだときに返されうる定跡手を、その順に列挙する。
+    /// `self` は変化しない。
+    ///
+    /// 戦型変更指示の対象となる場合、それ以降の定跡手は戦型変更後でないと決まらないので列挙しない。
+    pub fn candidates(&self, pos: &Position, progress_ply: u8) -> Vec<(BookEntryId, Move)> {
+        let mut cands = Vec::new();
+
+        if self.formation == Formation::Nothing {
+            return cands;
+        }
+
+        for i in bitop::iter_ones_u32(self.mask_unused_branch) {
+            match &self.formation.book_branch()[i as usize] {
+                BookBranchEntry::Move(bra_mv) => {
+                    if bra_mv.matches(pos) {
+                        cands.push((
+                            BookEntryId::Branch(i),
+                            Move::new_walk(bra_mv.src, bra_mv.dst),
+                        ));
+                    }
+                }
+                BookBranchEntry::ChangeFormation(bra_change) => {
+                    if bra_change.matches(pos, progress_ply) {
+                        return cands;
+                    }
+                }
+            }
+        }
+
+        for i in bitop::iter_ones_u32(self.mask_unused_moves) {
+            let e = &self.formation.book_moves()[i as usize];
+            cands.push((BookEntryId::Moves(i), Move::new_walk(e.src, e.dst)));
+        }
+
+        cands
+    }
+
+    /// `candidates()` が返した定跡手 `id` を使用済みにする。
+    ///
+    /// `next_move()` と同様、`progress_ply == 0` の場合は使用済みにならない。
+    pub fn consume(&mut self, id: BookEntryId, progress_ply: u8) {
+        if progress_ply == 0 {
+            return;
+        }
+
+        match id {
+            BookEntryId::Branch(i) => self.mask_unused_branch &= !(1 << i),
+            BookEntryId::Moves(i) => self.mask_unused_moves &= !(1 << i),
+        }
+    }
+}
+
+/// `BookState::candidates()` が返す定跡手の識別子。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BookEntryId {
+    /// 定跡分岐の i 個目の応手指示。
+    Branch(u32),
+
+    /// 定跡手順の i 個目の定跡手。
+    Moves(u32),
+}
+
+/// `dump_formation()` が返す、戦型 1 つ分の定跡。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FormationDump {
+    /// 定跡手順(応手指示がない場合に順に指される COM の指し手)。
+    pub main_line: Vec<Move>,
+
+    /// 定跡分岐の応手指示たち(定義順)。戦型変更指示は指し手を含まないので含まれない。
+    pub branches: Vec<BookBranchDump>,
+}
+
+/// 定跡分岐の応手指示 1 つ分。マス `sq` に HUM 駒 `pk` があったら `moves` で応じる。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BookBranchDump {
+    pub sq: Square,
+    pub pk: PieceKind,
+    pub moves: Vec<Move>,
+}
+
+/// 指定した戦型の定跡を返す。`formation` は `Nothing` であってはならない。
+///
+/// 戦型変更指示は含まれない(変更先の戦型について改めてこの関数を呼ぶこと)。
+/// いずれも COM の指し手のみで、間に入る HUM の指し手は含まない。
+pub fn dump_formation(formation: Formation) -> FormationDump {
+    assert_ne!(formation, Formation::Nothing);
+
+    let main_line = formation
+        .book_moves()
+        .iter()
+        .map(|e| Move::new_walk(e.src, e.dst))
+        .collect();
+
+    let branches = formation
+        .book_branch()
+        .iter()
+        .filter_map(|e| match e {
+            BookBranchEntry::Move(bra_mv) => Some(BookBranchDump {
+                sq: bra_mv.sq,
+                pk: bra_mv.pk,
+                moves: vec![Move::new_walk(bra_mv.src, bra_mv.dst)],
+            }),
+            BookBranchEntry::ChangeFormation(_) => None,
+        })
+        .collect();
+
+    FormationDump {
+        main_line,
+        branches,
+    }
 }
 
 /// 平手 中飛車 定跡分岐。
@@ -502,3 +613,109 @@ const BOOK_MOVES_COM_NIMAIOCHI: &[BookMovesEntry] = &[
     BookMovesEntry::new(SQ_31, SQ_42),
     BookMovesEntry::new(SQ_64, SQ_65),
 ];
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    use super::*;
+
+    #[test]
+    fn test_dump_formation() {
+        const FORMATIONS: [(Formation, Handicap); 8] = [
+            (Formation::Nakabisha, Handicap::HumSenteNakabisha),
+            (Formation::Sikenbisha, Handicap::HumSenteSikenbisha),
+            (Formation::Kakugawari, Handicap::HumSenteSikenbisha),
+            (Formation::Sujichigai, Handicap::HumSenteSikenbisha),
+            (Formation::HumHishaochi, Handicap::HumHishaochi),
+            (Formation::HumNimaiochi, Handicap::HumNimaiochi),
+            (Formation::ComHishaochi, Handicap::ComHishaochi),
+            (Formation::ComNimaiochi, Handicap::ComNimaiochi),
+        ];
+
+        for (formation, handicap) in FORMATIONS {
+            let dump = dump_formation(formation);
+            assert_eq!(dump.main_line.len(), formation.book_moves().len());
+
+            // 定跡手順は開始局面から COM の合法手として順に指せる。
+            // HUM の指し手は考慮しないので、HUM の駒を取る手もありうる。
+            let (_, mut board, hands) = handicap.startpos();
+            if matches!(formation, Formation::Kakugawari | Formation::Sujichigai) {
+                // 角交換で COM の角は既に取られている。
+                board[SQ_22] = NO_PIECE;
+            }
+            let mut boards = vec![board.clone()];
+            for &mv in &dump.main_line {
+                let mut pos = Position::new(COM, board.clone(), hands);
+                assert!(pos.legal_moves().contains(&mv), "{formation}: {mv:?}");
+                pos.do_move(mv);
+                board = pos.board().clone();
+                boards.push(board.clone());
+            }
+
+            // 各応手指示は、定跡手順を途中まで進めた局面のいずれかで、
+            // 契機となる HUM 駒を置けば COM の合法手として指せる。
+            // 契機となる HUM 駒は COM の駒を取ってそのマスに来ることもある(玉を除く)。
+            for branch in &dump.branches {
+                assert_eq!(branch.moves.len(), 1);
+                let mv = branch.moves[0];
+                let playable = boards.iter().any(|board| {
+                    if board[branch.sq] == Piece::new(COM, KING) {
+                        return false;
+                    }
+                    let mut board = board.clone();
+                    board[branch.sq] = Piece::new(HUM, branch.pk);
+                    let mut pos = Position::new(COM, board, hands);
+                    pos.legal_moves().contains(&mv)
+                });
+                assert!(playable, "{formation}: {branch:?}");
+            }
+        }
+
+        // 戦型変更指示は含まれない。
+        let branch_move_count = BOOK_BRANCH_SIKENBISHA
+            .iter()
+            .filter(|e| matches!(e, BookBranchEntry::Move(_)))
+            .count();
+        assert_eq!(
+            dump_formation(Formation::Sikenbisha).branches.len(),
+            branch_move_count
+        );
+    }
+
+    #[test]
+    fn test_book_state_candidates() {
+        let (_, board, hands) = Handicap::HumSenteSikenbisha.startpos();
+        let pos = Position::new(COM, board, hands);
+        let progress_ply = 2;
+
+        // next_move() を繰り返し呼んだときに返される定跡手と一致する。
+        let state = BookState::new(Formation::Sikenbisha);
+        let cands = state.candidates(&pos, progress_ply);
+        let mut state_seq = state;
+        let mut expected = Vec::new();
+        while let Some(mv) = state_seq.next_move(&pos, progress_ply) {
+            expected.push(mv);
+        }
+        assert_eq!(
+            cands.iter().map(|&(_, mv)| mv).collect::<Vec<_>>(),
+            expected
+        );
+        assert_eq!(state.candidates(&pos, progress_ply), cands);
+
+        // consume() は指定した定跡手のみを使用済みにする。
+        let mut state = state;
+        let (id, mv) = cands[2];
+        state.consume(id, progress_ply);
+        let cands_after = state.candidates(&pos, progress_ply);
+        assert_eq!(cands_after.len(), cands.len() - 1);
+        assert!(!cands_after.iter().any(|&(_, c)| c == mv));
+        assert_eq!(cands_after[..2], cands[..2]);
+
+        // progress_ply == 0 では使用済みにならない。
+        let mut state0 = BookState::new(Formation::Sikenbisha);
+        state0.consume(id, 0);
+        assert_eq!(state0.candidates(&pos, progress_ply), cands);
+    }
+}
@@ -163,12 +163,12 @@ pub fn snapshot_create() -> Snapshot {
     fceux::snapshot_create()
 }
 
-/// ステートセーブする。
+/// ステートロードする。
 pub fn snapshot_load(snap: &Snapshot) {
     fceux::snapshot_load(snap).expect("snapshot_load() failed");
 }
 
-/// ステートロードする。
+/// ステートセーブする。
 pub fn snapshot_save(snap: &Snapshot) {
     fceux::snapshot_save(snap).expect("snapshot_save() failed");
 }
@@ -1,4 +1,8 @@
 //! 原作固有の要素。
+//!
+//! 原作の思考ルーチンは乱数を使わない。また、思考の進行を示すフレームカウンタも RAM 上に見つかっていない。
+//! よって乱数状態やフレームカウンタを読み取る関数は提供しない。
+//! verify 時にエミュレータが想定通りの時点にあるかは、手数(`read_ply()`)と `addrs` の実行フックで確認すること。
 
 use crate::book::Formation;
 use crate::effect::EffectCountBoard;
@@ -7,8 +11,12 @@ use crate::myarray::*;
 use crate::naitou::*;
 use crate::shogi::*;
 
+use super::addrs;
 use super::apsp;
-use super::backend::{memory_read, Buttons, BUTTONS_A, BUTTONS_D, BUTTONS_S, BUTTONS_T};
+use super::backend::{
+    memory_read, run_frame_headless, run_frame_hooked_headless, Buttons, BUTTONS_A, BUTTONS_D,
+    BUTTONS_S, BUTTONS_T,
+};
 
 /// 盤面または HUM 側の手駒を指すカーソル。
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
@@ -453,6 +461,47 @@ pub fn inputs_move(mv: Move) -> Vec<Buttons> {
     inputs
 }
 
+/// HUM 側の指し手を順に指し、エミュレータを headless で進める。
+/// 呼ばれた時点で対局は開始済みでなければならない(HUM 側の指し手入力待ちループに入っている必要はない)。
+///
+/// 入力シーケンスは着手時のカーソル位置に依存し、また COM の思考時間は事前にわからないので、
+/// `inputs_move()` のように入力シーケンスを返すのではなく、エミュレータを直接動かす。
+/// 各指し手の前に HUM 側の指し手入力待ちループに入るまで待ち、最後の指し手の後も同様に待つ。
+/// よって、関数から戻ったときには最後の指し手に対する COM の応手が済んでいる。
+///
+/// 途中で終局するなどして指し手入力待ちループに入らない場合、エラーを返す。
+pub fn play_moves(mvs_hum: &[Move]) -> anyhow::Result<()> {
+    for &mv in mvs_hum {
+        wait_hum_turn_headless()?;
+        for buttons in inputs_move(mv) {
+            run_frame_headless(buttons);
+        }
+    }
+
+    wait_hum_turn_headless()
+}
+
+/// HUM 側の指し手入力待ちループに入るまでエミュレータを headless で進める。
+/// 一定フレーム数以内に入らなければエラーを返す。
+fn wait_hum_turn_headless() -> anyhow::Result<()> {
+    // COM の思考時間は長くとも数百フレーム程度なので、十分な余裕を持たせる。
+    const FRAME_LIMIT: u32 = 10000;
+
+    for _ in 0..FRAME_LIMIT {
+        let mut done = false;
+        run_frame_hooked_headless(Buttons::empty(), &|addr| {
+            if addr == addrs::HUM_TURN {
+                done = true;
+            }
+        });
+        if done {
+            return Ok(());
+        }
+    }
+
+    anyhow::bail!("HUM turn not reached within {} frames", FRAME_LIMIT);
+}
+
 /// HUM 側の指し手を (移動元カーソル位置, 移動先カーソル位置) に変換する。
 pub fn move_to_cursors(mv: Move) -> (Cursor, Cursor) {
     let cursor_dst = Cursor::new_board(mv.dst());
@@ -4,11 +4,15 @@
 
 use std::cmp::Ordering;
 
-use anyhow::bail;
+use anyhow::{bail, ensure};
+#[cfg(feature = "rand")]
+use rand::{rngs::StdRng, Rng as _};
 
 use crate::bbs;
 use crate::book::{BookState, Formation};
-use crate::movegen::{generate_moves_com, position_is_checkmated_naitou};
+use crate::effect::EffectCountBoard;
+use crate::kif::kif_encode_moves;
+use crate::movegen::{generate_moves_com, position_is_checkmated, position_is_checkmated_naitou};
 use crate::myarray::*;
 use crate::mylog::*;
 use crate::mynum::{WrappingAddAssign, WrappingSubAssign};
@@ -34,8 +38,21 @@ pub enum EngineResponse {
 }
 
 impl EngineResponse {
-    fn new_move(umv_com: UndoableMove, undo_info: EngineUndoInfo) -> Self {
-        Self::Move(EngineResponseMove { umv_com, undo_info })
+    fn new_move(
+        umv_com: UndoableMove,
+        resp_raw_move: EngineResponseRawMove,
+        fourfold: bool,
+        undo_info: EngineUndoInfo,
+    ) -> Self {
+        Self::Move(EngineResponseMove {
+            umv_com,
+            source: resp_raw_move.source,
+            eval: resp_raw_move.best_eval,
+            quiet: resp_raw_move.quiet,
+            force_skip_book: resp_raw_move.force_skip_book,
+            fourfold,
+            undo_info,
+        })
     }
 
     fn new_hum_win(undo_info: EngineUndoInfo) -> Self {
@@ -71,9 +88,41 @@ impl EngineResponse {
     }
 }
 
+/// COM の指し手の出所。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MoveSource {
+    /// 定跡手。
+    Book,
+
+    /// 探索による指し手。
+    Search,
+}
+
+/// HUM 側の指し手を `do_step()` が受け付けるかどうかの分類。`Engine::classify_hum_move()` を参照。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum HumMoveClass {
+    /// 合法手。受け付けられる。
+    Legal,
+
+    /// 自殺手。`do_step()` はエラーを返す。
+    Suicide,
+
+    /// 本来の将棋では違法だが、原作では受け付けられる手。
+    /// HUM 側の打ち歩詰めと、`set_emulate_book_check_bug(true)` としている場合の自殺手が該当する。
+    PseudoLegalButUnusual,
+
+    /// 終局後なので、指し手によらず受け付けられない。`do_step()` はエラーを返す。
+    GameOver,
+}
+
 #[derive(Debug)]
 pub struct EngineResponseMove {
-    umv_com: UndoableMove, // COM 側の指し手。
+    umv_com: UndoableMove,        // COM 側の指し手。
+    source: MoveSource,           // 指し手の出所。
+    eval: Option<LeafEvaluation>, // 探索で選ばれた手の評価。定跡手なら None。
+    quiet: bool,
+    force_skip_book: bool,
+    fourfold: bool, // COM の着手後の局面が 4 回以上現れた
     undo_info: EngineUndoInfo,
 }
 
@@ -82,6 +131,43 @@ impl EngineResponseMove {
     pub fn move_com(&self) -> UndoableMove {
         self.umv_com
     }
+
+    /// COM の指し手が定跡手か探索によるものかを返す。
+    pub fn source(&self) -> MoveSource {
+        self.source
+    }
+
+    /// COM の指し手を選んだ際の末端局面評価(評価修正後)を返す。
+    ///
+    /// 定跡手が採用された場合は評価が行われていないので `None` を返す。
+    pub fn evaluation(&self) -> Option<&LeafEvaluation> {
+        self.eval.as_ref()
+    }
+
+    /// 探索結果が "quiet" だったかどうかを返す。
+    ///
+    /// ルート局面で駒得マスも駒損マスもなく、かつ探索による最善手が駒取りでなければ "quiet" である。
+    /// 進行度 0 のとき、quiet でない探索結果が出るたびにサブ進行度が進む。
+    /// 定跡手が採用された場合は常に `false` を返す。
+    pub fn was_quiet(&self) -> bool {
+        self.quiet
+    }
+
+    /// 探索結果により定跡処理が強制的にスキップされたかどうかを返す。
+    ///
+    /// 有望な駒得マスが複数あると考えられる場合にスキップされる。
+    /// 定跡手が採用された場合は常に `false` を返す。
+    pub fn forced_skip_book(&self) -> bool {
+        self.force_skip_book
+    }
+
+    /// COM の着手後の局面が、開始局面からの対局中に 4 回以上現れたかどうかを返す。
+    ///
+    /// 同一局面(手番、盤面、両陣営の手駒が等しい)の出現回数を数える。連続王手かどうかは区別しない。
+    /// 真ならば千日手として引き分けにできる。思考エンジン自身は千日手を扱わないので、対局は続行できる。
+    pub fn is_fourfold(&self) -> bool {
+        self.fourfold
+    }
 }
 
 #[derive(Debug)]
@@ -110,7 +196,7 @@ impl EngineResponseComWin {
 /// 直前の局面の情報 (undo 用)。
 #[derive(Debug)]
 struct EngineUndoInfo {
-    umv_hum: UndoableMove, // HUM 側の指し手。
+    umv_hum: Option<UndoableMove>, // HUM 側の指し手。`step_com()` の場合は None。
     progress_ply: u8,
     progress_level: u8,
     progress_level_sub: u8,
@@ -119,7 +205,7 @@ struct EngineUndoInfo {
 }
 
 /// HUM 側の指し手に対する思考エンジンの応答。undo 用情報を含まない。
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 enum EngineResponseRaw {
     /// 指し手(COM の指し手で HUM 玉が詰んだケースを含む)。
     Move(EngineResponseRawMove),
@@ -131,9 +217,11 @@ enum EngineResponseRaw {
     HumSuicide,
 }
 
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 struct EngineResponseRawMove {
     best_mv: Move,
+    source: MoveSource,
+    best_eval: Option<LeafEvaluation>, // 定跡手なら None
     quiet: bool, // ルート局面で駒得マスも駒損マスもなく、かつ最善手が駒取りでない
     force_skip_book: bool, // 定跡処理を強制的にスキップ
     hum_is_checkmated: bool, // 最善手で HUM 玉が詰む
@@ -279,62 +367,287 @@ impl LeafEvaluation {
     }
 }
 
+/// 直前の思考における候補手の処理件数などの統計。プロファイリング用。
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ThinkStats {
+    /// 生成された候補手の数(違法手を含む)。
+    pub generated_count: u32,
+
+    /// 駒捨てとして却下された候補手の数。
+    pub rejected_by_sacrifice_count: u32,
+
+    /// 打ち歩詰めとして却下された候補手の数。
+    pub rejected_by_drop_pawn_mate_count: u32,
+
+    /// 却下されずに最善手と比較された候補手の数。
+    pub evaluated_count: u32,
+
+    /// 定跡手が採用されたかどうか。
+    pub book_move_adopted: bool,
+
+    /// 探索結果を受けて定跡処理がスキップされた場合、その理由。
+    pub book_skip_reason: Option<BookSkipReason>,
+}
+
+/// 探索結果を受けて定跡処理がスキップされた理由。`Engine::last_book_skip_reason()` を参照。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BookSkipReason {
+    /// 有望な駒得マスが複数あると考えられる(score_posi != adv_price かつ score_posi >= 8)。
+    MultipleTargets,
+
+    /// 探索結果が "quiet" でない。
+    NotQuiet,
+
+    /// 進行度が 0 でない(この思考中に進行度が上がった場合を含む)。
+    ProgressAdvanced,
+}
+
+/// 1 回の思考の過程を構造化して記録したもの。`Engine::do_step_traced()` で得られる。
+///
+/// ログ出力(エミュレータのログと照合するためのもの)と同じ内容を、ロガーを介さずに取得するためのもの。
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ThinkTrace {
+    /// ルート局面の評価。HUM の指し手が自殺手でない限り `Some`。
+    pub root_eval: Option<RootEvaluation>,
+
+    /// 探索で試した候補手たち(生成順)。
+    pub candidates: Vec<CandidateTrace>,
+
+    /// 定跡処理で検討した定跡手たち(検討順)。
+    pub book: Vec<BookTrace>,
+}
+
+/// 候補手 1 つ分の思考過程。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CandidateTrace {
+    /// 候補手。
+    pub mv: Move,
+
+    /// 初期評価としてログ出力される末端局面評価。候補手が却下された場合は `None`。
+    ///
+    /// 歩で駒を取る手の評価修正のみ適用済みで、他の評価修正は適用前。
+    pub initial_eval: Option<LeafEvaluation>,
+
+    /// 評価修正後の末端局面評価。候補手が却下された場合は `None`。
+    pub revised_eval: Option<LeafEvaluation>,
+
+    /// 候補手が最善手として採用されたかどうか。
+    pub improved: bool,
+
+    /// 採否を決めた比較項目。候補手が却下された場合は "rejected"。
+    pub reason: &'static str,
+}
+
+/// 定跡手 1 つ分の検討結果。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BookTrace {
+    /// 定跡手。
+    pub mv: Move,
+
+    /// 定跡手の採否。
+    pub decision: BookDecision,
+}
+
+/// 定跡手の採否。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BookDecision {
+    /// 採用された。
+    Accepted,
+
+    /// 違法手として却下された。
+    Illegal,
+
+    /// 移動先の利き数が勝っていないため却下された。
+    EffectNotSuperior,
+
+    /// 駒損するため却下された。
+    Disadvantage,
+
+    /// 採用可能だったが、乱数により読み飛ばされた(使用済みにはならない)。`Engine::set_book_rng()` を参照。
+    Skipped,
+}
+
+/// `Engine::new_with_options()` に渡す設定。既定値は原作通り。
+#[derive(Clone, Debug)]
+pub struct EngineOptions {
+    /// 投了判定の閾値。`Engine::set_resign_threshold()` を参照。
+    pub resign_threshold: Option<u8>,
+
+    /// 序盤を抜けるサブ進行度の閾値。`Engine::set_opening_exit_threshold()` を参照。
+    pub opening_exit_threshold: u8,
+
+    /// 原作の定跡処理による王手放置を再現するかどうか。`Engine::set_emulate_book_check_bug()` を参照。
+    pub emulate_book_check_bug: bool,
+
+    /// 定跡手の選択に使う乱数生成器。`Engine::set_book_rng()` を参照。
+    #[cfg(feature = "rand")]
+    pub book_rng: Option<StdRng>,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        Self {
+            resign_threshold: Some(Engine::RESIGN_THRESHOLD_DEFAULT),
+            opening_exit_threshold: Engine::OPENING_EXIT_THRESHOLD_DEFAULT,
+            emulate_book_check_bug: false,
+            #[cfg(feature = "rand")]
+            book_rng: None,
+        }
+    }
+}
+
 /// 原作を再現した思考エンジン。
 ///
 /// 基本的に HUM の手番の局面を保持する。
 /// ただし、COM が投了した場合と、HUM が自殺手を指した場合は例外。
 #[derive(Clone, Debug)]
 pub struct Engine {
+    handicap: Handicap,
     pos: Position,
     progress_ply: u8,       // 進行度管理用の手数 (0..=100)。開始局面では 0。
     progress_level: u8,     // 進行度 (0..=3)。
-    progress_level_sub: u8, // サブ進行度 (0..=5)。進行度 0 のときのみ使われる。
+    progress_level_sub: u8, // サブ進行度。進行度 0 のときのみ使われる。
     book_state: BookState,
 
     // 駒打ちの候補手と最善手を比較する際に必要となる値。
     // 原作ではこの値が局面ごとに初期化されないため、値を保持しておく必要がある。
     naitou_best_src_value: u8,
+
+    // 投了判定の閾値。最善手の disadv_price がこれ以上なら投了する。`None` なら投了しない。
+    resign_threshold: Option<u8>,
+
+    // サブ進行度がこれ以上になったら進行度 1 とする。
+    opening_exit_threshold: u8,
+
+    // 直前の思考の統計。
+    think_stats: ThinkStats,
+
+    // 原作の定跡処理による王手放置を再現するかどうか。
+    emulate_book_check_bug: bool,
+
+    // 開始局面からの全ての指し手。
+    game_moves: Vec<UndoableMove>,
+
+    // 終局したかどうか。
+    terminal: bool,
+
+    // 思考過程の記録先。`do_step_traced()` の間のみ `Some`。
+    trace: Option<ThinkTrace>,
+
+    // 定跡手の選択に使う乱数生成器。`None` なら原作通り最初に採用可能な定跡手を選ぶ。
+    #[cfg(feature = "rand")]
+    book_rng: Option<StdRng>,
 }
 
 impl Engine {
+    /// 投了判定の閾値の既定値(原作通り)。
+    pub const RESIGN_THRESHOLD_DEFAULT: u8 = 31;
+
+    /// 序盤を抜けるサブ進行度の閾値の既定値(原作通り)。
+    pub const OPENING_EXIT_THRESHOLD_DEFAULT: u8 = 5;
+
     /// 指定した手合割で初期化された思考エンジンを返す。
     /// COM が先に指す手合割の場合、COM の着手も行い、その指し手も返す。
     pub fn new(handicap: Handicap) -> (Self, Option<UndoableMove>) {
+        let (engine, umv_com) = Self::new_with_options(handicap, EngineOptions::default());
+        assert!(!engine.terminal, "the first move should be a normal move");
+
+        (engine, umv_com)
+    }
+
+    /// `new()` と同様だが、設定を指定して初期化する。設定は COM の初手にも適用される。
+    ///
+    /// 設定によっては COM が初手で投了することもありうる。その場合は COM の指し手として `None` を返し、終局となる。
+    pub fn new_with_options(
+        handicap: Handicap,
+        options: EngineOptions,
+    ) -> (Self, Option<UndoableMove>) {
+        let mut engine = Self::new_unstarted(handicap);
+        engine.resign_threshold = options.resign_threshold;
+        engine.opening_exit_threshold = options.opening_exit_threshold;
+        engine.emulate_book_check_bug = options.emulate_book_check_bug;
+        #[cfg(feature = "rand")]
+        {
+            engine.book_rng = options.book_rng;
+        }
+
+        let umv_com = engine.play_first_com_move();
+
+        (engine, umv_com)
+    }
+
+    /// 指定した手合割で初期化された思考エンジンを返す。COM が先に指す手合割でも COM の着手は行わない。
+    fn new_unstarted(handicap: Handicap) -> Self {
         let (side_to_move, board, hands) = handicap.startpos();
         let pos = Position::new(side_to_move, board, hands);
 
         let formation = Formation::from_handicap(handicap);
         let book_state = BookState::new(formation);
 
-        let mut engine = Self {
+        Self {
+            handicap,
             pos,
             progress_ply: 0,
             progress_level: 0,
             progress_level_sub: 0,
             book_state,
             naitou_best_src_value: 0,
-        };
+            resign_threshold: Some(Self::RESIGN_THRESHOLD_DEFAULT),
+            opening_exit_threshold: Self::OPENING_EXIT_THRESHOLD_DEFAULT,
+            think_stats: ThinkStats::default(),
+            emulate_book_check_bug: false,
+            game_moves: Vec::new(),
+            terminal: false,
+            trace: None,
+            #[cfg(feature = "rand")]
+            book_rng: None,
+        }
+    }
 
-        // COM が先に指す場合、その着手を行い、指し手を取得する。
-        let umv_com = if engine.pos.side_to_move() == HUM {
-            None
-        } else {
-            let resp_raw = engine.think(None);
-            // 初手は通常の指し手のはず。
-            if let EngineResponseRaw::Move(resp_raw_move) = resp_raw {
-                // TODO: ログ出力コードが分散して汚いのでどうにかしたいが...
-                log_engine_response_move(resp_raw_move.best_mv);
-                log_think_end();
-                Some(engine.do_move_com(resp_raw_move.best_mv))
-            } else {
-                panic!(
-                    "the first move should be a normal move, but got: {:?}",
-                    resp_raw
-                );
-            }
+    /// 開始局面が COM の手番ならば COM の着手を行い、その指し手を返す。
+    ///
+    /// 設定によっては COM が初手で投了することもありうる。その場合は `None` を返し、終局となる。
+    fn play_first_com_move(&mut self) -> Option<UndoableMove> {
+        if self.pos.side_to_move() == HUM {
+            return None;
+        }
+
+        self.step_com()
+            .expect("step_com() should succeed at the initial position")
+            .move_com()
+    }
+
+    /// 初期化時に指定した手合割を返す。
+    pub fn handicap(&self) -> Handicap {
+        self.handicap
+    }
+
+    /// 終局したかどうかを返す。
+    ///
+    /// 直前の `do_step()` が `EngineResponse::Move` 以外を返した場合に終局となる。
+    /// 終局後は `do_step()` はエラーを返すので、`undo_step()` で戻すか `reset_to_root()` で開始局面に戻すこと。
+    pub fn is_terminal(&self) -> bool {
+        self.terminal
+    }
+
+    /// 思考エンジンを `Engine::new()` 直後の状態に戻す。
+    /// COM が先に指す手合割の場合、COM の着手も行い、その指し手も返す。
+    ///
+    /// 投了判定の閾値などの設定は保持され、COM の初手にも適用される。
+    /// 設定によって COM が初手で投了した場合は `None` を返し、終局となる。
+    pub fn reset_to_root(&mut self) -> Option<UndoableMove> {
+        let options = EngineOptions {
+            resign_threshold: self.resign_threshold,
+            opening_exit_threshold: self.opening_exit_threshold,
+            emulate_book_check_bug: self.emulate_book_check_bug,
+            #[cfg(feature = "rand")]
+            book_rng: self.book_rng.take(),
         };
 
-        (engine, umv_com)
+        let (engine, umv_com) = Self::new_with_options(self.handicap, options);
+        *self = engine;
+
+        umv_com
     }
 
     /// 現在の局面への参照を返す。
@@ -343,6 +656,130 @@ impl Engine {
         &self.pos
     }
 
+    /// 現在の局面における両陣営の手駒への参照を返す。`position().hands()` と同じ。
+    pub fn hands(&self) -> &Hands {
+        self.pos.hands()
+    }
+
+    /// 指定した陣営が取った駒のうち、まだ打っていないもの(つまり現在の手駒)を返す。
+    ///
+    /// 開始局面では手駒は常に空なので、手駒はそれまでに取った駒から打った駒を除いたものと一致する。
+    pub fn captured_by(&self, side: Side) -> Hand {
+        *self.pos.hand(side)
+    }
+
+    /// 現在の局面における (HUM, COM) の利き数盤面を返す。
+    ///
+    /// UI で利きのヒートマップを描画する用途を想定している。
+    pub fn effect_overlay(&self) -> (EffectCountBoard, EffectCountBoard) {
+        (
+            self.pos.effect_count_board(HUM).clone(),
+            self.pos.effect_count_board(COM).clone(),
+        )
+    }
+
+    /// HUM が `mv_hum` を指し、COM が候補手 `mv_com` を指した後の局面における (HUM, COM) の利き数盤面を返す。
+    ///
+    /// 局面は HUM の手番なので、COM の候補手を適用するには HUM の指し手も必要となる。
+    /// 思考エンジンの状態は変更しない。
+    ///
+    /// `mv_com` は `mv_hum` を指した局面で疑似合法手でなければならない。
+    /// `mv_hum` が自殺手の場合、または終局後に呼んだ場合、エラーを返す。
+    pub fn effect_overlay_after(
+        &self,
+        mv_hum: Move,
+        mv_com: Move,
+    ) -> anyhow::Result<(EffectCountBoard, EffectCountBoard)> {
+        ensure!(!self.terminal, "game is already over");
+
+        let mut engine = self.clone();
+        engine.do_move_hum(mv_hum)?;
+        engine.pos.do_move(mv_com);
+
+        Ok(engine.effect_overlay())
+    }
+
+    /// HUM が `mv_hum` を指した局面において、COM の候補手 `mv_com` が自殺手(COM 玉に王手がかかったままになる手)かどうかを返す。
+    ///
+    /// 末端局面評価における `LeafEvaluation::is_suicide` と同じ判定で、
+    /// 本プログラムは玉を取る手に対応していないため、最善手が自殺手ならば HUM 勝ちとする安全策に使われている。
+    /// 局面は HUM の手番なので、COM の候補手を適用するには HUM の指し手も必要となる。
+    /// 思考エンジンの状態は変更しない。
+    ///
+    /// `mv_com` は `mv_hum` を指した局面で疑似合法手でなければならない。
+    /// `mv_hum` が自殺手の場合、または終局後に呼んだ場合、エラーを返す。
+    pub fn candidate_is_suicide(&self, mv_hum: Move, mv_com: Move) -> anyhow::Result<bool> {
+        ensure!(!self.terminal, "game is already over");
+
+        let mut engine = self.clone();
+        engine.do_move_hum(mv_hum)?;
+        engine.pos.do_move(mv_com);
+
+        Ok(engine.pos.is_checked(COM))
+    }
+
+    /// HUM が `mv_hum` を指した局面において、COM の候補手 `mv_com` に対する「成駒を HUM 玉に近づける手」の評価修正値を返す。
+    ///
+    /// 成駒を動かす手の場合、(移動元と HUM 玉の距離) - (移動先と HUM 玉の距離) が score_posi に加算される。
+    /// 駒打ちや成駒でない駒を動かす手にはこの修正は適用されないので、0 を返す。
+    /// 思考エンジンの状態は変更しない。
+    ///
+    /// `mv_com` は `mv_hum` を指した局面で疑似合法手でなければならない。
+    /// `mv_hum` が自殺手の場合、または終局後に呼んだ場合、エラーを返す。
+    pub fn promoted_walk_bonus(&self, mv_hum: Move, mv_com: Move) -> anyhow::Result<i8> {
+        ensure!(!self.terminal, "game is already over");
+
+        let mut engine = self.clone();
+        engine.do_move_hum(mv_hum)?;
+        let hum_king_sq = engine.pos.king_square(HUM);
+        let umv_com = engine.pos.do_move(mv_com);
+
+        // score_posi には u8 としてラップアラウンド加算されるので、符号付きで解釈したものを返す。
+        Ok(Self::promoted_walk_value(umv_com, hum_king_sq).map_or(0, |value| value as i8))
+    }
+
+    /// 末端局面評価 `a`, `b` を持つ 2 つの COM の候補手を、エンジンの思考と同じ基準で比較する。
+    ///
+    /// `b` が暫定最善手のとき `a` がそれを置き換えるなら `Ordering::Greater`、さもなくば `Ordering::Less` を返す。
+    /// `Ordering::Equal` は返さない。`a_drop`, `b_drop` はそれぞれの候補手が駒打ちならその駒種、さもなくば `None`。
+    /// `a`, `b` は評価修正済みのものでなければならない。思考ログは出力しない。
+    ///
+    /// 原作の比較は全順序ではない(反対称性も推移性も成り立たないことがある)ので、
+    /// ソートに使う場合は結果が比較順序に依存しうることに注意。
+    pub fn compare_candidates(
+        root_eval: &RootEvaluation,
+        a: &LeafEvaluation,
+        a_drop: Option<PieceKind>,
+        b: &LeafEvaluation,
+        b_drop: Option<PieceKind>,
+    ) -> Ordering {
+        let _quiet = quiet();
+
+        // HUM 玉を詰ます手は無条件に採用される。
+        let improved = a.hum_is_checkmated || {
+            let best_src_value = b_drop.map_or(0, naitou_com_drop_src_value);
+            Self::compare_leaf_evaluations(root_eval, b, best_src_value, a, a_drop).0
+        };
+
+        if improved {
+            Ordering::Greater
+        } else {
+            Ordering::Less
+        }
+    }
+
+    /// 開始局面から現在の局面までの全ての指し手を返す。
+    ///
+    /// COM が先に指す手合割の場合、COM の初手も含む。
+    pub fn game_moves(&self) -> &[UndoableMove] {
+        &self.game_moves
+    }
+
+    /// 開始局面から現在の局面までの棋譜を KIF 形式の指し手部分として返す。
+    pub fn kif_record(&self) -> String {
+        kif_encode_moves(&self.game_moves)
+    }
+
     /// 進行度管理用の手数を返す。
     pub fn progress_ply(&self) -> u8 {
         self.progress_ply
@@ -363,113 +800,513 @@ impl Engine {
         &self.book_state
     }
 
-    /// HUM 側の指し手とそれに対する COM の応手(あれば)で局面を進め、思考エンジンの応答を返す。
-    ///
-    /// `mv_hum` は少なくとも疑似合法手でなければならない。これが自殺手の場合、エラーを返す。
-    /// (原作通り、HUM 側の打ち歩詰めは許される)
+    /// 駒打ちの候補手と最善手を比較する際に使われる内部値を返す。
     ///
-    /// `self` が保持する局面は HUM の手番でなければならない。
-    pub fn do_step(&mut self, mv_hum: Move) -> anyhow::Result<EngineResponse> {
-        let undo_info = self.do_move_hum(mv_hum)?;
+    /// 原作ではこの値が局面ごとに初期化されず、以前の局面での値が持ち越される。
+    /// その挙動を確認するためのもので、値自体は原作とは異なる(盤上の駒を動かす手なら 0)。
+    pub fn naitou_best_src_value(&self) -> u8 {
+        self.naitou_best_src_value
+    }
 
-        let resp_raw = self.think(Some(mv_hum));
+    /// 現在の局面がルート局面の基準で "quiet"(駒得マスも駒損マスもない)かどうかを返す。
+    ///
+    /// 現在の盤面をそのまま(HUM がパスしたものとして)ルート局面として評価する。
+    /// 探索による最善手が駒取りでなければ、この値は探索結果の `EngineResponseMove::was_quiet()` と一致する。
+    pub fn is_position_quiet(&self) -> bool {
+        let root_eval = self.evaluate_root();
 
-        let resp = match resp_raw {
-            EngineResponseRaw::Move(resp_raw_move) => {
-                let mv_com = resp_raw_move.best_mv;
-                let umv_com = self.do_move_com(mv_com);
-                // 最善手を指した局面で HUM 玉が詰みなら COM 勝ち。
-                if resp_raw_move.hum_is_checkmated {
-                    log_engine_response_com_win(mv_com);
-                    EngineResponse::new_com_win(umv_com, undo_info)
-                } else {
-                    log_engine_response_move(mv_com);
-                    EngineResponse::new_move(umv_com, undo_info)
-                }
-            }
-            EngineResponseRaw::HumWin => {
-                log_engine_response_hum_win();
-                EngineResponse::new_hum_win(undo_info)
-            }
-            EngineResponseRaw::HumSuicide => {
-                log_engine_response_hum_suicide();
-                EngineResponse::new_hum_suicide(undo_info)
-            }
-        };
+        root_eval.adv_price == 0 && root_eval.disadv_price == 0
+    }
 
-        log_think_end();
+    /// 現在の局面における指定した陣営の戦力(`RootEvaluation::power_hum`, `RootEvaluation::power_com`)を返す。
+    ///
+    /// `is_position_quiet()` と同様、現在の盤面をそのままルート局面として評価する。
+    /// 手駒と成駒から計算され、手数補正も含む。
+    pub fn power(&self, side: Side) -> u8 {
+        let root_eval = self.evaluate_root();
 
-        Ok(resp)
+        if side == HUM {
+            root_eval.power_hum
+        } else {
+            root_eval.power_com
+        }
     }
 
-    /// COM 側の局面で思考を行い、`EngineResponseRaw` を返す。局面は進めない。
-    #[inline]
-    fn think(&mut self, mv_hum: Option<Move>) -> EngineResponseRaw {
-        log_think_start(self.pos.ply());
-        log_position(self.pos.side_to_move(), self.pos.board(), self.pos.hands());
-        log_effect_count_board(HUM, self.pos.effect_count_board(HUM));
-        log_effect_count_board(COM, self.pos.effect_count_board(COM));
-        log_progress(
-            self.progress_ply,
-            self.progress_level,
-            self.progress_level_sub,
-        );
-        log_formation(self.book_state.formation());
+    /// 直前の思考の統計を返す。
+    ///
+    /// `do_step()` がエラーを返した場合、統計は更新されない。
+    pub fn last_think_stats(&self) -> ThinkStats {
+        self.think_stats
+    }
 
-        // ルート局面を評価。
-        let root_eval = self.evaluate_root();
-        log_root_evaluation(&root_eval);
+    /// 直前の思考において、探索結果を受けて定跡処理がスキップされた場合、その理由を返す。
+    ///
+    /// 定跡処理が行われた場合(定跡が尽きた場合を含む)、および探索結果が指し手でなかった場合は `None` を返す。
+    pub fn last_book_skip_reason(&self) -> Option<BookSkipReason> {
+        self.think_stats.book_skip_reason
+    }
 
-        // 探索による思考を行う。
-        let resp_raw = self.think_search(&root_eval);
+    /// 投了判定の閾値を返す。
+    pub fn resign_threshold(&self) -> Option<u8> {
+        self.resign_threshold
+    }
 
-        // 以下の条件を全て満たすとき、think_search() の結果によらず定跡処理を行う:
-        //
-        // * progress_ply <= 6
-        // * HUM の指し手の移動先が２二, ４五, ５六のいずれか
-        // * progress_level == 0
-        //
-        // XXX: 原作ではこれにより王手放置ができてしまう手順がある。
-        // 本プログラムでは HUM 側の自殺手は生成しないので影響はない。
-        if let Some(mv_hum) = mv_hum {
-            let dst = mv_hum.dst();
-            if self.progress_ply <= 6
-                && (dst == SQ_22 || dst == SQ_45 || dst == SQ_56)
-                && self.progress_level == 0
-            {
-                if let Some(book_mv) = self.think_book(Some(mv_hum)) {
-                    return EngineResponseRaw::Move(EngineResponseRawMove {
-                        best_mv: book_mv,
-                        quiet: false,           // 使われない
-                        force_skip_book: false, // 使われない
-                        hum_is_checkmated: false,
-                    });
-                }
-                // 定跡手が尽きたら進行度 1 とする(もう定跡が使われることはない)。
-                self.progress_level = 1;
-            }
-        }
+    /// 投了判定の閾値を設定する。
+    ///
+    /// 最善手の disadv_price が `threshold` 以上のとき COM は投了する。
+    /// `None` を指定すると投了しなくなり、最も悪くない手を指し続ける。
+    /// ただし、最善手が自殺手の場合は常に投了する(本プログラムは玉を取る手に対応していないため)。
+    ///
+    /// 既定値は `Some(Engine::RESIGN_THRESHOLD_DEFAULT)` (原作通り)。
+    pub fn set_resign_threshold(&mut self, threshold: Option<u8>) {
+        self.resign_threshold = threshold;
+    }
 
-        // 探索により指し手が返された場合、定跡も検討する。
-        if let EngineResponseRaw::Move(resp_raw_move) = resp_raw {
-            // 進行度 0 のとき、quiet でない指し手が返されるたびにサブ進行度を進める。
-            // サブ進行度が 5 になったら進行度 1 とする。
-            if self.progress_level == 0 && !resp_raw_move.quiet {
-                self.progress_level_sub += 1;
-                if self.progress_level_sub >= 5 {
-                    self.progress_level = 1;
-                }
-            }
+    /// 序盤を抜けるサブ進行度の閾値を返す。
+    pub fn opening_exit_threshold(&self) -> u8 {
+        self.opening_exit_threshold
+    }
+
+    /// 序盤を抜けるサブ進行度の閾値を設定する。
+    ///
+    /// 進行度 0 のとき、quiet でない探索結果が出るたびにサブ進行度が進み、
+    /// サブ進行度が `threshold` 以上になると進行度 1 となる(以後定跡は使われない)。
+    /// 値を小さくすると COM は早く定跡を抜け、大きくすると長く定跡に留まる。
+    ///
+    /// 既定値は `Engine::OPENING_EXIT_THRESHOLD_DEFAULT` (原作通り)。
+    pub fn set_opening_exit_threshold(&mut self, threshold: u8) {
+        self.opening_exit_threshold = threshold;
+    }
+
+    /// 定跡手の選択に使う乱数生成器を設定する。
+    ///
+    /// `Some` を設定すると、定跡処理の際に採用可能な定跡手が複数あれば、そのうちの 1 つを無作為に選ぶ。
+    /// 選ばれなかった定跡手は使用済みにならないので、定跡手順が崩れることはない。
+    /// 探索による思考には影響しない。既定値は `None` で、原作通り最初に採用可能な定跡手を選ぶ。
+    /// `reset_to_root()` しても設定は保持される。
+    ///
+    /// COM が先に指す手合割で初手から無作為に選ばせるには、`new_with_options()` で設定すること。
+    #[cfg(feature = "rand")]
+    pub fn set_book_rng(&mut self, rng: Option<StdRng>) {
+        self.book_rng = rng;
+    }
+
+    /// 原作の定跡処理による王手放置を再現するかどうかを返す。
+    pub fn emulate_book_check_bug(&self) -> bool {
+        self.emulate_book_check_bug
+    }
+
+    /// 原作の定跡処理による王手放置を再現するかどうかを設定する。既定値は `false`。
+    ///
+    /// 原作では、以下の条件を全て満たすとき探索結果によらず定跡処理が行われる:
+    ///
+    /// * progress_ply <= 6
+    /// * HUM の指し手の移動先が２二, ４五, ５六のいずれか
+    /// * progress_level == 0
+    ///
+    /// このため、HUM が上記の条件を満たす自殺手を指しても、定跡手が残っていれば COM は玉を取らずに定跡手を指す。
+    /// 有効にすると `do_step()` は HUM の自殺手を受け付け、この挙動を再現する。
+    /// 定跡手が採用されなかった場合は `EngineResponse::HumSuicide` を返す。
+    ///
+    /// 原作との完全一致を調べる目的でのみ使うこと。
+    pub fn set_emulate_book_check_bug(&mut self, enabled: bool) {
+        self.emulate_book_check_bug = enabled;
+    }
+
+    /// HUM 側の疑似合法手 `mv` を `do_step()` が受け付けるかどうかを分類して返す。
+    ///
+    /// 局面は一時的に進めるが、呼び出し前の状態に戻してから返る。
+    /// `self` が保持する局面は HUM の手番でなければならない。終局後に呼んだ場合、`HumMoveClass::GameOver` を返す。
+    pub fn classify_hum_move(&mut self, mv: Move) -> HumMoveClass {
+        if self.terminal {
+            return HumMoveClass::GameOver;
+        }
+
+        debug_assert_eq!(self.pos.side_to_move(), HUM);
+
+        let umv = self.pos.do_move(mv);
+
+        let class = if self.pos.is_checked(HUM) {
+            // do_move_hum() と同じ判定。
+            if self.emulate_book_check_bug {
+                HumMoveClass::PseudoLegalButUnusual
+            } else {
+                HumMoveClass::Suicide
+            }
+        } else if mv.is_drop()
+            && mv.dropped_piece_kind() == PAWN
+            && self.pos.is_checked(COM)
+            && position_is_checkmated(&mut self.pos)
+        {
+            // 原作では HUM 側の打ち歩詰めも受け付ける。
+            HumMoveClass::PseudoLegalButUnusual
+        } else {
+            HumMoveClass::Legal
+        };
+
+        self.pos.undo_move(umv);
+
+        class
+    }
+
+    /// HUM 側の指し手 `mv_hum` を指した局面において、COM の候補手 `mv_com` が
+    /// 「寄せが見込めない状況ではむやみに王手をかけない」という評価修正の対象となるかどうかを返す。
+    ///
+    /// 思考エンジンの状態は変更せず、思考ログも出力しない。候補手が評価前に却下される場合(駒捨てなど)は `false` を返す。
+    /// `mv_hum` が自殺手の場合、または終局後に呼んだ場合、エラーを返す。
+    pub fn would_suppress_check(&self, mv_hum: Move, mv_com: Move) -> anyhow::Result<bool> {
+        let _quiet = quiet();
+
+        ensure!(!self.terminal, "game is already over");
+
+        let mut engine = self.clone();
+        engine.do_move_hum(mv_hum)?;
+
+        let root_eval = engine.evaluate_root();
+        let umv = engine.pos.do_move(mv_com);
+
+        // この評価修正より前の修正で判定条件に関わる項目が変わるのは、最大駒得マスが互いの玉から遠い場合のみ。
+        // adv_price >= 30 ならば最大駒得マスは HUM 玉の位置なので、修正前の評価で判定してよい。
+        let suppressed = engine
+            .evaluate_leaf(&root_eval, umv)
+            .is_some_and(|leaf_eval| Self::is_useless_check(&root_eval, &leaf_eval));
+
+        Ok(suppressed)
+    }
+
+    /// 現局面における HUM の指し手を、原作の評価関数で良い順に並べて返す(ヒント用)。
+    ///
+    /// 局面を反転し、HUM の指し手を COM の候補手とみなして評価する。
+    /// 先頭要素は、反転局面で COM が探索により選ぶ手と一致する。
+    /// 2 番目以降は、それより上位の手を除いた候補手の中から同様に最善手を選ぶことを繰り返して決める。
+    ///
+    /// 以下の制限がある:
+    ///
+    /// * 進行度、定跡などの状態は COM 側のものであり、反転局面にもそのまま使われる。
+    ///   よって定跡は考慮せず、手数依存の評価も COM 側の進行度に基づく。
+    /// * 評価関数により却下される手(駒捨て、打ち歩詰めなど)、および自殺手は結果に含まれない。
+    ///
+    /// 思考エンジンの状態は変更せず、思考ログも出力しない。
+    pub fn rank_hum_moves(&self) -> Vec<(Move, LeafEvaluation)> {
+        let _quiet = quiet();
+
+        let mut engine = self.clone();
+        engine.pos = self.pos.flipped();
+        engine.trace = None;
+
+        let root_eval = engine.evaluate_root();
+        let best_src_value = engine.naitou_best_src_value;
+
+        let mut mvs: Vec<_> = generate_moves_com(&engine.pos).into_iter().collect();
+        let mut ranking = Vec::with_capacity(mvs.len());
+        loop {
+            engine.naitou_best_src_value = best_src_value;
+            let Some((i, leaf_eval)) = engine.select_best_move(&root_eval, &mvs) else {
+                break;
+            };
+            let mv = mvs.remove(i);
+            if !leaf_eval.is_suicide {
+                ranking.push((mv.rotate180(), leaf_eval));
+            }
+        }
+
+        ranking
+    }
+
+    /// `think_search()` と同じ基準で、候補手 `mvs` のうち最善手のインデックスとその評価を返す。
+    ///
+    /// 全ての候補手が却下された場合、`None` を返す。
+    fn select_best_move(
+        &mut self,
+        root_eval: &RootEvaluation,
+        mvs: &[Move],
+    ) -> Option<(usize, LeafEvaluation)> {
+        let mut best: Option<usize> = None;
+        let mut best_eval = LeafEvaluation::worst();
+
+        for (i, &mv) in mvs.iter().enumerate() {
+            let umv = self.pos.do_move(mv);
+
+            if let Some(mut leaf_eval) = self.evaluate_leaf(root_eval, umv) {
+                self.revise_leaf_evaluation(root_eval, umv, &mut leaf_eval);
+
+                let hum_is_checkmated = leaf_eval.hum_is_checkmated;
+                let improved = hum_is_checkmated
+                    || self
+                        .compare_with_best(root_eval, &best_eval, &leaf_eval, umv)
+                        .0;
+                if improved {
+                    best = Some(i);
+                    best_eval = leaf_eval;
+                    self.naitou_best_src_value = if umv.is_drop() {
+                        naitou_com_drop_src_value(umv.dropped_piece_kind())
+                    } else {
+                        0
+                    };
+                }
+
+                // HUM 玉の詰みが見つかったら打ち切る。
+                if hum_is_checkmated {
+                    self.pos.undo_move(umv);
+                    break;
+                }
+            }
+
+            self.pos.undo_move(umv);
+        }
+
+        best.map(|i| (i, best_eval))
+    }
+
+    /// HUM 側の指し手 `mv_hum` を指した局面において、COM の候補手 `mv_com` が駒捨てとして却下されるかどうかを返す。
+    ///
+    /// 候補手の移動先が駒損マスで、かつ駒取りでない場合は駒捨てとみなされる。
+    /// 駒捨ては、ルート局面で COM 玉に王手がかかっている(disadv_price >= 30)か、HUM 玉を詰ます手でない限り却下される。
+    /// よって、COM は駒を捨てて寄せるような手を一切指さない。
+    ///
+    /// 思考エンジンの状態は変更せず、思考ログも出力しない。
+    /// `mv_hum` が自殺手の場合、または終局後に呼んだ場合、エラーを返す。
+    pub fn is_rejected_as_sacrifice(&self, mv_hum: Move, mv_com: Move) -> anyhow::Result<bool> {
+        let _quiet = quiet();
+
+        ensure!(!self.terminal, "game is already over");
+
+        let mut engine = self.clone();
+        engine.do_move_hum(mv_hum)?;
+
+        let root_eval = engine.evaluate_root();
+        let umv = engine.pos.do_move(mv_com);
+
+        // evaluate_leaf() と全く同じ判定をするため、駒捨てによる却下の件数が増えたかどうかを見る。
+        let count_before = engine.think_stats.rejected_by_sacrifice_count;
+        let _ = engine.evaluate_leaf(&root_eval, umv);
+        let rejected = engine.think_stats.rejected_by_sacrifice_count > count_before;
+
+        Ok(rejected)
+    }
+
+    /// HUM 側の指し手 `mv_hum` を指した局面において、COM の候補手 `a`, `b` を評価し、
+    /// 一方を最善手としたとき他方がそれを更新するかどうかを双方向について調べ、その説明文字列を返す。
+    ///
+    /// 説明には各候補手の末端局面評価と、判定を決めた比較項目が含まれる。
+    /// 思考エンジンの状態は変更せず、思考ログも出力しない。
+    /// `mv_hum` が自殺手の場合、または終局後に呼んだ場合、エラーを返す。
+    pub fn debug_compare(&self, mv_hum: Move, a: Move, b: Move) -> anyhow::Result<String> {
+        use std::fmt::Write as _;
+
+        fn evaluate(
+            engine: &mut Engine,
+            root_eval: &RootEvaluation,
+            mv: Move,
+        ) -> (UndoableMove, Option<LeafEvaluation>) {
+            let umv = engine.pos.do_move(mv);
+            let leaf_eval = engine.evaluate_leaf(root_eval, umv).map(|mut leaf_eval| {
+                engine.revise_leaf_evaluation(root_eval, umv, &mut leaf_eval);
+                leaf_eval
+            });
+            engine.pos.undo_move(umv);
+
+            (umv, leaf_eval)
+        }
+
+        ensure!(!self.terminal, "game is already over");
+
+        let _quiet = quiet();
+
+        let mut engine = self.clone();
+        engine.do_move_hum(mv_hum)?;
+
+        let root_eval = engine.evaluate_root();
+        let (umv_a, eval_a) = evaluate(&mut engine, &root_eval, a);
+        let (umv_b, eval_b) = evaluate(&mut engine, &root_eval, b);
+
+        let mut out = String::new();
+        for (name, mv, leaf_eval) in [("a", a, &eval_a), ("b", b, &eval_b)] {
+            match leaf_eval {
+                Some(leaf_eval) => writeln!(out, "{name}: {mv}: {leaf_eval:?}").unwrap(),
+                None => writeln!(out, "{name}: {mv}: rejected").unwrap(),
+            }
+        }
+
+        if let (Some(eval_a), Some(eval_b)) = (&eval_a, &eval_b) {
+            for (cand_name, umv_cand, eval_cand, best_name, umv_best, eval_best) in [
+                ("b", umv_b, eval_b, "a", umv_a, eval_a),
+                ("a", umv_a, eval_a, "b", umv_b, eval_b),
+            ] {
+                // 思考ルーチンと同様、最善手が駒打ちならその移動元の値を設定しておく。
+                engine.naitou_best_src_value = if umv_best.is_drop() {
+                    naitou_com_drop_src_value(umv_best.dropped_piece_kind())
+                } else {
+                    0
+                };
+                let (improved, reason) =
+                    engine.compare_with_best(&root_eval, eval_best, eval_cand, umv_cand);
+                writeln!(
+                    out,
+                    "{cand_name} vs best {best_name}: {} (decided by {reason})",
+                    if improved { "improved" } else { "not improved" }
+                )
+                .unwrap();
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// HUM 側の指し手とそれに対する COM の応手(あれば)で局面を進め、思考エンジンの応答を返す。
+    ///
+    /// `mv_hum` は少なくとも疑似合法手でなければならない。これが自殺手の場合、エラーを返す。
+    /// (原作通り、HUM 側の打ち歩詰めは許される)
+    /// ただし、`set_emulate_book_check_bug(true)` としている場合は自殺手も受け付ける。
+    ///
+    /// `self` が保持する局面は HUM の手番でなければならない。終局後に呼んだ場合、エラーを返す。
+    pub fn do_step(&mut self, mv_hum: Move) -> anyhow::Result<EngineResponse> {
+        ensure!(!self.terminal, "game is already over");
+
+        let undo_info = self.do_move_hum(mv_hum)?;
+
+        let resp_raw = self.think(Some(mv_hum));
+
+        Ok(self.respond(resp_raw, undo_info))
+    }
+
+    /// 思考結果 `resp_raw` に従って COM の着手を行い、`EngineResponse` を返す。
+    ///
+    /// 応答が `EngineResponse::Move` 以外なら終局とする。
+    fn respond(
+        &mut self,
+        resp_raw: EngineResponseRaw,
+        undo_info: EngineUndoInfo,
+    ) -> EngineResponse {
+        let resp = match resp_raw {
+            EngineResponseRaw::Move(resp_raw_move) => {
+                let mv_com = resp_raw_move.best_mv;
+                let umv_com = self.do_move_com(mv_com);
+                // 最善手を指した局面で HUM 玉が詰みなら COM 勝ち。
+                if resp_raw_move.hum_is_checkmated {
+                    log_engine_response_com_win(mv_com);
+                    EngineResponse::new_com_win(umv_com, undo_info)
+                } else {
+                    log_engine_response_move(mv_com);
+                    let fourfold = self.repetition_count() >= 4;
+                    EngineResponse::new_move(umv_com, resp_raw_move, fourfold, undo_info)
+                }
+            }
+            EngineResponseRaw::HumWin => {
+                log_engine_response_hum_win();
+                EngineResponse::new_hum_win(undo_info)
+            }
+            EngineResponseRaw::HumSuicide => {
+                log_engine_response_hum_suicide();
+                EngineResponse::new_hum_suicide(undo_info)
+            }
+        };
+
+        log_think_end();
+
+        self.terminal = !matches!(resp, EngineResponse::Move(_));
+
+        resp
+    }
+
+    /// `do_step()` と同様だが、思考過程を記録した `ThinkTrace` も返す。
+    ///
+    /// HUM の指し手が自殺手でエラーとなる場合、思考過程は捨てられる。
+    pub fn do_step_traced(&mut self, mv_hum: Move) -> anyhow::Result<(EngineResponse, ThinkTrace)> {
+        self.trace = Some(ThinkTrace::default());
+        let resp = self.do_step(mv_hum);
+        let trace = self.trace.take().expect("trace should exist");
+
+        Ok((resp?, trace))
+    }
+
+    /// COM 側の局面で思考を行い、`EngineResponseRaw` を返す。局面は進めない。
+    #[inline]
+    fn think(&mut self, mv_hum: Option<Move>) -> EngineResponseRaw {
+        self.think_stats = ThinkStats::default();
+
+        log_think_start(self.pos.ply());
+        log_position(self.pos.side_to_move(), self.pos.board(), self.pos.hands());
+        log_effect_count_board(HUM, self.pos.effect_count_board(HUM));
+        log_effect_count_board(COM, self.pos.effect_count_board(COM));
+        log_progress(
+            self.progress_ply,
+            self.progress_level,
+            self.progress_level_sub,
+        );
+        log_formation(self.book_state.formation());
+
+        // ルート局面を評価。
+        let root_eval = self.evaluate_root();
+        log_root_evaluation(&root_eval);
+        if let Some(trace) = &mut self.trace {
+            trace.root_eval = Some(root_eval.clone());
+        }
+
+        // 探索による思考を行う。
+        let resp_raw = self.think_search(&root_eval);
+
+        // 以下の条件を全て満たすとき、think_search() の結果によらず定跡処理を行う:
+        //
+        // * progress_ply <= 6
+        // * HUM の指し手の移動先が２二, ４五, ５六のいずれか
+        // * progress_level == 0
+        //
+        // XXX: 原作ではこれにより王手放置ができてしまう手順がある。
+        // 本プログラムでは HUM 側の自殺手は受け付けないので影響はない。
+        // ただし emulate_book_check_bug が有効な場合は自殺手を受け付けるので、原作の挙動が再現される。
+        if let Some(mv_hum) = mv_hum {
+            let dst = mv_hum.dst();
+            if self.progress_ply <= 6
+                && (dst == SQ_22 || dst == SQ_45 || dst == SQ_56)
+                && self.progress_level == 0
+            {
+                if let Some(book_mv) = self.think_book(Some(mv_hum)) {
+                    return EngineResponseRaw::Move(EngineResponseRawMove {
+                        best_mv: book_mv,
+                        source: MoveSource::Book,
+                        best_eval: None,
+                        quiet: false,           // 使われない
+                        force_skip_book: false, // 使われない
+                        hum_is_checkmated: false,
+                    });
+                }
+                // 定跡手が尽きたら進行度 1 とする(もう定跡が使われることはない)。
+                self.progress_level = 1;
+            }
+        }
+
+        // 探索により指し手が返された場合、定跡も検討する。
+        if let EngineResponseRaw::Move(resp_raw_move) = &resp_raw {
+            // 進行度 0 のとき、quiet でない指し手が返されるたびにサブ進行度を進める。
+            // サブ進行度が閾値(既定値 5)になったら進行度 1 とする。
+            if self.progress_level == 0 && !resp_raw_move.quiet {
+                self.progress_level_sub += 1;
+                if self.progress_level_sub >= self.opening_exit_threshold {
+                    self.progress_level = 1;
+                }
+            }
 
             // 以下の条件を全て満たすとき定跡処理を行う:
             //
             // * 進行度が 0
             // * 指し手が quiet
             // * 定跡処理強制スキップフラグが立っていない
-            if self.progress_level == 0 && resp_raw_move.quiet && !resp_raw_move.force_skip_book {
+            self.think_stats.book_skip_reason = if self.progress_level != 0 {
+                Some(BookSkipReason::ProgressAdvanced)
+            } else if !resp_raw_move.quiet {
+                Some(BookSkipReason::NotQuiet)
+            } else if resp_raw_move.force_skip_book {
+                Some(BookSkipReason::MultipleTargets)
+            } else {
+                None
+            };
+            if self.think_stats.book_skip_reason.is_none() {
                 if let Some(book_mv) = self.think_book(mv_hum) {
                     return EngineResponseRaw::Move(EngineResponseRawMove {
                         best_mv: book_mv,
+                        source: MoveSource::Book,
+                        best_eval: None,
                         quiet: false,           // 使われない
                         force_skip_book: false, // 使われない
                         hum_is_checkmated: false,
@@ -497,8 +1334,10 @@ impl Engine {
         let mut best_eval = LeafEvaluation::worst();
 
         // 全候補手を生成し、順に試す。
+        let mvs = generate_moves_com(&self.pos);
+        self.think_stats.generated_count = mvs.len() as u32;
         let mut done = false;
-        for mv in generate_moves_com(&self.pos) {
+        for mv in mvs {
             // 候補手を適用した末端局面を評価する。
             let umv = self.pos.do_move(mv);
 
@@ -509,9 +1348,24 @@ impl Engine {
 
             let leaf_eval = self.evaluate_leaf(root_eval, umv);
 
+            let mut cand_trace = self.trace.is_some().then_some(CandidateTrace {
+                mv,
+                initial_eval: None,
+                revised_eval: None,
+                improved: false,
+                reason: "rejected",
+            });
+
             // 候補手が却下されていなければ、評価修正および最善手との比較を行う。
             if let Some(mut leaf_eval) = leaf_eval {
-                self.revise_leaf_evaluation(root_eval, umv, &mut leaf_eval);
+                self.think_stats.evaluated_count += 1;
+
+                // 初期評価はログ出力と同じ時点のものを記録する。
+                Self::revise_leaf_evaluation_ini(umv, &mut leaf_eval);
+                if let Some(cand_trace) = &mut cand_trace {
+                    cand_trace.initial_eval = Some(leaf_eval.clone());
+                }
+                self.revise_leaf_evaluation_main(root_eval, umv, &mut leaf_eval);
                 log_leaf_evaluation_revised(&leaf_eval);
 
                 let hum_is_checkmated = leaf_eval.hum_is_checkmated;
@@ -521,9 +1375,19 @@ impl Engine {
                     log_cmp_start();
                 }
 
-                if hum_is_checkmated
-                    || self.can_improve_best(root_eval, &best_eval, &leaf_eval, umv)
-                {
+                let (improved, reason) = if hum_is_checkmated {
+                    (true, "hum_is_checkmated")
+                } else {
+                    self.compare_with_best(root_eval, &best_eval, &leaf_eval, umv)
+                };
+
+                if let Some(cand_trace) = &mut cand_trace {
+                    cand_trace.revised_eval = Some(leaf_eval.clone());
+                    cand_trace.improved = improved;
+                    cand_trace.reason = reason;
+                }
+
+                if improved {
                     best_mv = Some(mv);
                     best_eval = leaf_eval;
                     // naitou_best_src_value を更新する。
@@ -545,6 +1409,10 @@ impl Engine {
             log_best(best_mv, &best_eval);
             log_cand_end();
 
+            if let (Some(trace), Some(cand_trace)) = (&mut self.trace, cand_trace) {
+                trace.candidates.push(cand_trace);
+            }
+
             self.pos.undo_move(umv);
 
             if done {
@@ -559,13 +1427,22 @@ impl Engine {
         // (実際には取っても何も起こらず、次の手で COM 玉が復活する)
         // 本プログラムは玉を取る手には対応していないので、
         // 最善手が自殺手の場合も HUM 勝ちにするという条件を追加している。
-        if best_eval.disadv_price >= 31 || best_eval.is_suicide {
+        //
+        // 投了判定の閾値は変更可能(既定値は原作通り 31)。閾値が `None` なら投了しない。
+        let resigns = matches!(
+            self.resign_threshold,
+            Some(threshold) if best_eval.disadv_price >= threshold
+        );
+        if resigns || best_eval.is_suicide {
             return EngineResponseRaw::HumWin;
         }
 
         // HUM が自殺手を指しておらず、HUM 勝ちでもなければ指し手を返す。
-        // 少なくとも 1 つの最善手があるはず。
-        let best_mv = best_mv.expect("at least 1 move should be accepted");
+        // 投了しない設定の場合は全ての候補手が却下されることもありうるので、そのときは HUM 勝ちとする。
+        let best_mv = match best_mv {
+            Some(best_mv) => best_mv,
+            None => return EngineResponseRaw::HumWin,
+        };
 
         // ルート局面で駒損マスも駒得マスもなく、かつ最善手が駒取りでなければ "quiet" である。
         let quiet =
@@ -577,9 +1454,11 @@ impl Engine {
 
         EngineResponseRaw::Move(EngineResponseRawMove {
             best_mv,
+            source: MoveSource::Search,
             quiet,
             force_skip_book,
             hum_is_checkmated: best_eval.hum_is_checkmated,
+            best_eval: Some(best_eval),
         })
     }
 
@@ -740,6 +1619,7 @@ impl Engine {
             if umv.is_drop() && umv.dropped_piece_kind() == PAWN {
                 // 打ち歩詰めは即却下。
                 log_cand_reject_by_drop_pawn_mate();
+                self.think_stats.rejected_by_drop_pawn_mate_count += 1;
                 return None;
             } else {
                 // HUM 玉が詰みなら、他の候補手に上書きされないよう評価を修正する。
@@ -760,6 +1640,7 @@ impl Engine {
         // 王手対応や詰ます手でなければ駒捨ては却下。
         if sacrifice && root_eval.disadv_price < 30 && !leaf_eval.hum_is_checkmated {
             log_cand_reject_by_sacrifice();
+            self.think_stats.rejected_by_sacrifice_count += 1;
             return None;
         }
 
@@ -847,6 +1728,15 @@ impl Engine {
         Some(leaf_eval)
     }
 
+    /// 指し手の移動先の駒種を返す。駒打ちかどうかを問わない。
+    fn piece_kind_dst(umv: UndoableMove) -> PieceKind {
+        if umv.is_drop() {
+            umv.dropped_piece_kind()
+        } else {
+            umv.piece_dst().kind()
+        }
+    }
+
     /// 様々な要素を勘案して末端局面の評価を修正する。
     #[inline]
     fn revise_leaf_evaluation(
@@ -855,30 +1745,45 @@ impl Engine {
         umv: UndoableMove,
         leaf_eval: &mut LeafEvaluation,
     ) {
-        let hum_king_sq = root_eval.king_sq[HUM];
-        let com_king_sq = root_eval.king_sq[COM];
-
-        let dst_to_com_king = umv.dst().distance(com_king_sq);
-
-        // 指し手の移動先の駒種。駒打ちかどうかを問わない。
-        let pk_dst = if umv.is_drop() {
-            umv.dropped_piece_kind()
-        } else {
-            umv.piece_dst().kind()
-        };
+        Self::revise_leaf_evaluation_ini(umv, leaf_eval);
+        self.revise_leaf_evaluation_main(root_eval, umv, leaf_eval);
+    }
 
+    /// 末端局面評価の修正のうち、初期評価のログ出力より前に行われるものを適用する。
+    ///
+    /// verify の都合上、これを適用した末端局面評価を初期評価としてログ出力する。
+    #[inline]
+    fn revise_leaf_evaluation_ini(umv: UndoableMove, leaf_eval: &mut LeafEvaluation) {
         // COM 側の玉、龍、馬が取られず、歩(不成)で駒を取る手の評価を上げる。
-        if leaf_eval.disadv_price < 20 && leaf_eval.capture_price > 0 && pk_dst == PAWN {
+        if leaf_eval.disadv_price < 20
+            && leaf_eval.capture_price > 0
+            && Self::piece_kind_dst(umv) == PAWN
+        {
             log_revise_capture_by_pawn(leaf_eval);
             leaf_eval.score_nega.wrapping_sub_assign(1);
         }
 
-        // verify の都合上、ここでの末端局面評価を初期評価としてログ出力する。
         log_leaf_evaluation_ini(leaf_eval);
+    }
 
-        // HUM 側の垂れ歩または垂れ香が存在すれば評価を下げる。
-        if leaf_eval.hum_hanging {
-            log_revise_hum_hanging(leaf_eval);
+    /// `revise_leaf_evaluation_ini()` 以降の末端局面評価の修正を適用する。
+    #[inline]
+    fn revise_leaf_evaluation_main(
+        &self,
+        root_eval: &RootEvaluation,
+        umv: UndoableMove,
+        leaf_eval: &mut LeafEvaluation,
+    ) {
+        let hum_king_sq = root_eval.king_sq[HUM];
+        let com_king_sq = root_eval.king_sq[COM];
+
+        let dst_to_com_king = umv.dst().distance(com_king_sq);
+
+        let pk_dst = Self::piece_kind_dst(umv);
+
+        // HUM 側の垂れ歩または垂れ香が存在すれば評価を下げる。
+        if leaf_eval.hum_hanging {
+            log_revise_hum_hanging(leaf_eval);
             leaf_eval.score_nega.wrapping_add_assign(4);
         }
 
@@ -936,12 +1841,7 @@ impl Engine {
 
         // 寄せが見込めない状況ではむやみに王手をかけない。
         // ただし「王手xx取り」を除く。
-        if leaf_eval.adv_price >= 30
-            && leaf_eval.hum_king_threat_around25 < 12
-            && root_eval.rbp_com < 4
-            && root_eval.power_com < 35
-            && leaf_eval.score_posi.wrapping_sub(leaf_eval.adv_price) < 3
-        {
+        if Self::is_useless_check(root_eval, leaf_eval) {
             log_revise_useless_check(leaf_eval);
             leaf_eval
                 .score_posi
@@ -1086,12 +1986,8 @@ impl Engine {
         }
 
         // 成駒を動かす場合、HUM 玉に近づく手の方を高く評価する。
-        if !umv.is_drop() && umv.piece_src().is_promoted() {
+        if let Some(value) = Self::promoted_walk_value(umv, hum_king_sq) {
             log_revise_promoted_walk(leaf_eval);
-            let value = umv
-                .src()
-                .distance(hum_king_sq)
-                .wrapping_sub(umv.dst().distance(hum_king_sq));
             leaf_eval.score_posi.wrapping_add_assign(value);
         }
 
@@ -1121,30 +2017,71 @@ impl Engine {
         saturate_negative(&mut leaf_eval.score_nega);
     }
 
-    /// 候補手が現在の最善手より優れているかどうかを返す。
+    /// 末端局面評価 `leaf_eval` の候補手が寄せの見込めない王手(「王手xx取り」を除く)かどうかを返す。
     #[inline]
-    fn can_improve_best(
+    fn is_useless_check(root_eval: &RootEvaluation, leaf_eval: &LeafEvaluation) -> bool {
+        leaf_eval.adv_price >= 30
+            && leaf_eval.hum_king_threat_around25 < 12
+            && root_eval.rbp_com < 4
+            && root_eval.power_com < 35
+            && leaf_eval.score_posi.wrapping_sub(leaf_eval.adv_price) < 3
+    }
+
+    /// 成駒を動かす手 `umv` に対し、HUM 玉に近づく度合いに応じた score_posi への加算値を返す。
+    /// 成駒を動かす手でなければ `None` を返す。
+    #[inline]
+    fn promoted_walk_value(umv: UndoableMove, hum_king_sq: Square) -> Option<u8> {
+        (!umv.is_drop() && umv.piece_src().is_promoted()).then(|| {
+            umv.src()
+                .distance(hum_king_sq)
+                .wrapping_sub(umv.dst().distance(hum_king_sq))
+        })
+    }
+
+    /// 候補手が現在の最善手より優れているかどうかと、その判定を決めた比較項目を返す。
+    #[inline]
+    fn compare_with_best(
         &self,
         root_eval: &RootEvaluation,
         best_eval: &LeafEvaluation,
         leaf_eval: &LeafEvaluation,
         umv: UndoableMove,
-    ) -> bool {
+    ) -> (bool, &'static str) {
+        Self::compare_leaf_evaluations(
+            root_eval,
+            best_eval,
+            self.naitou_best_src_value,
+            leaf_eval,
+            umv.is_drop().then(|| umv.dropped_piece_kind()),
+        )
+    }
+
+    /// `compare_with_best()` の本体。エンジンの状態に依存しない。
+    ///
+    /// `best_src_value` は最善手についての `naitou_best_src_value` の値。
+    /// `cand_drop` は候補手が駒打ちならその駒種、さもなくば `None`。
+    fn compare_leaf_evaluations(
+        root_eval: &RootEvaluation,
+        best_eval: &LeafEvaluation,
+        best_src_value: u8,
+        leaf_eval: &LeafEvaluation,
+        cand_drop: Option<PieceKind>,
+    ) -> (bool, &'static str) {
         /// タイブレーク処理。
         ///
-        /// * `lhs > rhs` ならば `true` を返す。
-        /// * `lhs < rhs` ならば `false` を返す。
+        /// * `lhs > rhs` ならば `(true, reason)` を返す。
+        /// * `lhs < rhs` ならば `(false, reason)` を返す。
         /// * `lhs == rhs` ならば何もせず次の処理に移る。
         macro_rules! tie_break_with_log {
-            ($lhs:expr, $rhs:expr, $f_log:expr) => {{
+            ($lhs:expr, $rhs:expr, $f_log:expr, $reason:expr) => {{
                 match $lhs.cmp(&$rhs) {
                     Ordering::Greater => {
                         $f_log(true);
-                        return true;
+                        return (true, $reason);
                     }
                     Ordering::Less => {
                         $f_log(false);
-                        return false;
+                        return (false, $reason);
                     }
                     Ordering::Equal => {}
                 }
@@ -1157,13 +2094,13 @@ impl Engine {
         // 後の最善手更新のされ方によっては COM 玉が詰んでないのに投了することもありうる。
         if leaf_eval.disadv_price >= 40 && best_eval.disadv_price < 40 {
             log_cmp_suicide(false);
-            return false;
+            return (false, "suicide");
         }
 
         // 候補手が自殺手でなく最善手が自殺手なら、明らかに候補手を採用すべき。
         if leaf_eval.disadv_price < 40 && best_eval.disadv_price >= 40 {
             log_cmp_suicide(true);
-            return true;
+            return (true, "suicide");
         }
 
         // まず score_nega を比較する。
@@ -1181,7 +2118,7 @@ impl Engine {
                 // 候補手の方が capture_price も悪いなら、明らかに最善手を採用すべき。
                 Ordering::Less => {
                     log_cmp_nega_worse_capture_price_worse();
-                    return false;
+                    return (false, "score_nega worse, capture_price worse");
                 }
 
                 // 候補手の方が capture_price が良いなら、
@@ -1191,7 +2128,7 @@ impl Engine {
                     let dnega = leaf_eval.score_nega - best_eval.score_nega;
                     let improved = dcapture >= dnega;
                     log_cmp_nega_worse_capture_price_better(improved);
-                    return improved;
+                    return (improved, "score_nega worse, capture_price better");
                 }
 
                 // capture_price が等しい場合、大抵は最善手の方が良いと考えられる。
@@ -1210,7 +2147,7 @@ impl Engine {
                         false
                     };
                     log_cmp_nega_worse_capture_price_equal(improved);
-                    return improved;
+                    return (improved, "score_nega worse, capture_price equal");
                 }
             },
 
@@ -1220,7 +2157,7 @@ impl Engine {
                 // 最善手の score_nega が大きすぎるなら直ちに候補手を採用する。
                 if (30..80).contains(&best_eval.score_nega) {
                     log_cmp_nega_better_extreme();
-                    return true;
+                    return (true, "score_nega better, best score_nega extreme");
                 }
 
                 // capture_price を比較。
@@ -1228,7 +2165,7 @@ impl Engine {
                     // 候補手の方が capture_price も良いなら、明らかに候補手を採用すべき。
                     Ordering::Greater => {
                         log_cmp_nega_better_capture_price_better();
-                        return true;
+                        return (true, "score_nega better, capture_price better");
                     }
 
                     // 候補手の方が capture_price が悪い場合、その差分を score_nega の差分と比較して決める。
@@ -1239,7 +2176,8 @@ impl Engine {
                         tie_break_with_log!(
                             dnega,
                             dcapture,
-                            log_cmp_nega_better_capture_price_worse
+                            log_cmp_nega_better_capture_price_worse,
+                            "score_nega better, capture_price worse"
                         );
                     }
 
@@ -1258,11 +2196,12 @@ impl Engine {
                             tie_break_with_log!(
                                 dnega,
                                 dposi,
-                                log_cmp_nega_better_capture_price_equal
+                                log_cmp_nega_better_capture_price_equal,
+                                "score_nega better, capture_price equal"
                             );
                         } else {
                             log_cmp_nega_better_capture_price_equal(true);
-                            return true;
+                            return (true, "score_nega better, capture_price equal");
                         }
                     }
                 }
@@ -1273,7 +2212,8 @@ impl Engine {
                 tie_break_with_log!(
                     leaf_eval.capture_price,
                     best_eval.capture_price,
-                    log_cmp_nega_equal
+                    log_cmp_nega_equal,
+                    "score_nega equal, capture_price"
                 );
             }
         }
@@ -1284,35 +2224,42 @@ impl Engine {
         tie_break_with_log!(
             leaf_eval.com_promo_count,
             best_eval.com_promo_count,
-            log_cmp_com_promo_count
+            log_cmp_com_promo_count,
+            "com_promo_count"
         );
 
         // score_posi が異なるなら、良い方を採用。
         tie_break_with_log!(
             leaf_eval.score_posi,
             best_eval.score_posi,
-            log_cmp_score_posi
+            log_cmp_score_posi,
+            "score_posi"
         );
 
         // adv_price が異なるなら、良い方を採用。
-        tie_break_with_log!(leaf_eval.adv_price, best_eval.adv_price, log_cmp_adv_price);
+        tie_break_with_log!(
+            leaf_eval.adv_price,
+            best_eval.adv_price,
+            log_cmp_adv_price,
+            "adv_price"
+        );
 
-        if umv.is_drop() {
+        if let Some(pk) = cand_drop {
             // 合駒でない限り、駒打ちより盤上の駒を動かす手を優先する。
             if root_eval.disadv_price < 30 {
                 log_cmp_prefer_walk();
-                return false;
+                return (false, "prefer walk to drop");
             }
             // 合駒の場合、より安い駒を打つ手なら採用する。
             //
             // XXX: naitou_best_src_value は局面ごとに初期化されないため、
             // 場合によっては以前の局面に影響されることもありうる。
-            let value = naitou_com_drop_src_value(umv.dropped_piece_kind());
-            let improved = value < self.naitou_best_src_value;
+            let value = naitou_com_drop_src_value(pk);
+            let improved = value < best_src_value;
             if improved {
                 log_cmp_drop_prefer_cheap();
             }
-            improved
+            (improved, "drop src value")
         } else {
             // 盤上の駒を動かす手同士を雑多な項目で比較する。
 
@@ -1320,26 +2267,30 @@ impl Engine {
             tie_break_with_log!(
                 leaf_eval.hum_king_threat_around25,
                 best_eval.hum_king_threat_around25,
-                log_cmp_walk_hum_king_threat_around25
+                log_cmp_walk_hum_king_threat_around25,
+                "hum_king_threat_around25"
             );
 
             // COM 玉の危険度に差があるなら、良い方を採用。
             tie_break_with_log!(
                 leaf_eval.com_king_safety_around25,
                 best_eval.com_king_safety_around25,
-                log_cmp_walk_com_king_safety_around25
+                log_cmp_walk_com_king_safety_around25,
+                "com_king_safety_around25"
             );
             tie_break_with_log!(
                 best_eval.com_king_threat_around25,
                 leaf_eval.com_king_threat_around25,
-                log_cmp_walk_com_king_threat_around25
+                log_cmp_walk_com_king_threat_around25,
+                "com_king_threat_around25"
             );
 
             // 離れ駒の個数が異なるなら、少ない方を採用。
             tie_break_with_log!(
                 best_eval.com_loose_count,
                 leaf_eval.com_loose_count,
-                log_cmp_walk_com_loose_count
+                log_cmp_walk_com_loose_count,
+                "com_loose_count"
             );
 
             // COM 玉から遠い駒を動かす候補手の場合、移動先が最善手より HUM 玉に近ければ採用。
@@ -1347,7 +2298,8 @@ impl Engine {
                 tie_break_with_log!(
                     best_eval.dst_to_hum_king,
                     leaf_eval.dst_to_hum_king,
-                    log_cmp_walk_dst_to_hum_king
+                    log_cmp_walk_dst_to_hum_king,
+                    "dst_to_hum_king"
                 );
             }
 
@@ -1355,7 +2307,7 @@ impl Engine {
             // 自玉周りの駒はなるべく動かさない意図か。
             let improved = leaf_eval.src_to_com_king > best_eval.src_to_com_king;
             log_cmp_walk_src_to_com_king(improved);
-            improved
+            (improved, "src_to_com_king")
         }
     }
 
@@ -1364,51 +2316,126 @@ impl Engine {
     fn think_book(&mut self, mv_hum: Option<Move>) -> Option<Move> {
         log_book_start();
 
+        // 乱数生成器が設定されている場合、採用可能な定跡手から無作為に選ぶ。
+        #[cfg(feature = "rand")]
+        if let Some(book_mv) = self.choose_random_book_move(mv_hum) {
+            log_book_accept_move(book_mv);
+            self.think_stats.book_move_adopted = true;
+            return Some(book_mv);
+        }
+
         // いずれかの定跡手が採用されるか、もしくは定跡手が尽きるまでループ。
         // 却下された定跡手も book_state からは捨てられることに注意。
         loop {
             // 定跡手を取得。定跡手が尽きたら終了。
             let book_mv = self.book_state.next_move(&self.pos, self.progress_ply)?;
 
-            // 違法手は却下。
-            if !self.book_move_is_legal(book_mv) {
-                continue;
-            }
+            let decision = self.judge_book_move(book_mv, mv_hum);
 
-            // 移動先の利き数が勝っていなければ却下。
-            if self.pos.effect_count_board(HUM)[book_mv.dst()]
-                >= self.pos.effect_count_board(COM)[book_mv.dst()]
-            {
-                continue;
-            }
-
-            // 定跡手を指した局面を評価し、駒損するなら原則として却下。
-            // ただし、progress_ply <= 6 かつ直前の HUM の指し手の移動先が４五の場合のみ却下しない。
-            // これは裏技的要素と思われる(いきなり右桂を跳ね出して５三を破る手が成立する)。
-            let disadv_price = self.evaluate_book_move(book_mv);
-            if disadv_price > 0
-                && !(self.progress_ply <= 6 && mv_hum.map_or(false, |mv| mv.dst() == SQ_45))
-            {
+            self.trace_book(book_mv, decision);
+            if decision != BookDecision::Accepted {
                 continue;
             }
 
             // 全てのチェックを通ったら定跡手を採用。
             log_book_accept_move(book_mv);
+            self.think_stats.book_move_adopted = true;
             return Some(book_mv);
         }
     }
 
-    /// 定跡手が合法かどうかを返す。
+    /// 定跡手 `book_mv` を採用するかどうかを判定する。
+    fn judge_book_move(&mut self, book_mv: Move, mv_hum: Option<Move>) -> BookDecision {
+        // 違法手は却下。
+        if !self.book_move_is_legal(book_mv) {
+            return BookDecision::Illegal;
+        }
+
+        // 移動先の利き数が勝っていなければ却下。
+        if self.pos.effect_count_board(HUM)[book_mv.dst()]
+            >= self.pos.effect_count_board(COM)[book_mv.dst()]
+        {
+            return BookDecision::EffectNotSuperior;
+        }
+
+        // 定跡手を指した局面を評価し、駒損するなら原則として却下。
+        // ただし、progress_ply <= 6 かつ直前の HUM の指し手の移動先が４五の場合のみ却下しない。
+        // これは裏技的要素と思われる(いきなり右桂を跳ね出して５三を破る手が成立する)。
+        let disadv_price = self.evaluate_book_move(book_mv);
+        if disadv_price > 0
+            && !(self.progress_ply <= 6 && mv_hum.map_or(false, |mv| mv.dst() == SQ_45))
+        {
+            return BookDecision::Disadvantage;
+        }
+
+        BookDecision::Accepted
+    }
+
+    /// 乱数生成器が設定されていれば、採用可能な定跡手から 1 つを無作為に選び、それのみを使用済みにして返す。
+    ///
+    /// 選ばれなかった定跡手は使用済みにしないので、読み飛ばした定跡手順の手も後で指されうる。
+    /// 選ばれた定跡手より前に並ぶ採用可能な定跡手は、読み飛ばしとして思考過程に記録される。
+    ///
+    /// 乱数生成器が設定されていない場合、採用可能な定跡手が 2 つ未満の場合、
+    /// または先頭の採用可能な定跡手が選ばれた場合は `None` を返し、通常の定跡処理に任せる。
+    #[cfg(feature = "rand")]
+    fn choose_random_book_move(&mut self, mv_hum: Option<Move>) -> Option<Move> {
+        self.book_rng.as_ref()?;
+
+        let accepted: Vec<_> = self
+            .book_state
+            .candidates(&self.pos, self.progress_ply)
+            .into_iter()
+            .filter(|&(_, book_mv)| self.judge_book_move(book_mv, mv_hum) == BookDecision::Accepted)
+            .collect();
+        if accepted.len() < 2 {
+            return None;
+        }
+
+        let chosen = self.book_rng.as_mut()?.gen_range(0..accepted.len());
+        if chosen == 0 {
+            return None;
+        }
+
+        for &(_, book_mv) in &accepted[..chosen] {
+            self.trace_book(book_mv, BookDecision::Skipped);
+        }
+
+        let (id, book_mv) = accepted[chosen];
+        self.book_state.consume(id, self.progress_ply);
+        self.trace_book(book_mv, BookDecision::Accepted);
+
+        Some(book_mv)
+    }
+
+    /// 思考過程を記録中なら、定跡手の採否を記録する。
+    #[inline]
+    fn trace_book(&mut self, mv: Move, decision: BookDecision) {
+        if let Some(trace) = &mut self.trace {
+            trace.book.push(BookTrace { mv, decision });
+        }
+    }
+
+    /// COM 側の指し手 `mv` が定跡手として合法かどうかを原作準拠で判定する。
+    ///
+    /// 定跡手は駒打ちや成りを含まず、行きどころのない駒も生じないので、そうした手は常に違法とみなす。
+    /// 手番は考慮しない。
     #[inline]
-    fn book_move_is_legal(&self, mv: Move) -> bool {
+    pub fn book_move_is_legal(&self, mv: Move) -> bool {
         // 定跡手に駒打ちは含まれない。
-        debug_assert!(!mv.is_drop());
+        if mv.is_drop() {
+            return false;
+        }
 
         // 定跡手は全て不成である。
-        debug_assert!(!mv.is_promotion());
+        if mv.is_promotion() {
+            return false;
+        }
 
         // 定跡手で行きどころのない駒が生じることはない。
-        debug_assert!(mv.dst().row() <= ROW_7);
+        if mv.dst().row() > ROW_7 {
+            return false;
+        }
 
         // 移動先に COM 駒があってはならない。
         let pc_dst = self.pos.board()[mv.dst()];
@@ -1436,6 +2463,26 @@ impl Engine {
         true
     }
 
+    /// HUM が `mv_hum` を指した局面において、COM 側の指し手 `mv_com` を定跡手として指した局面の
+    /// disadv_price (駒損の度合い)を原作準拠で返す。定跡処理ではこれが 0 でない定跡手は原則として却下される。
+    ///
+    /// 思考エンジンの状態は変更しない。
+    /// `mv_hum` が自殺手の場合、`mv_hum` を指した局面で `mv_com` が `book_move_is_legal()` を満たさない場合、
+    /// または終局後に呼んだ場合、エラーを返す。
+    pub fn book_move_disadv(&self, mv_hum: Move, mv_com: Move) -> anyhow::Result<u8> {
+        ensure!(!self.terminal, "game is already over");
+
+        let mut engine = self.clone();
+        engine.do_move_hum(mv_hum)?;
+        ensure!(
+            engine.book_move_is_legal(mv_com),
+            "not a legal book move: {}",
+            mv_com
+        );
+
+        Ok(engine.evaluate_book_move(mv_com))
+    }
+
     /// 定跡手を指した局面を評価し、disadv_price を返す。
     #[inline]
     fn evaluate_book_move(&mut self, mv: Move) -> u8 {
@@ -1559,23 +2606,71 @@ impl Engine {
         })
     }
 
-    /// `do_step()` を undo し、元の状態を復元する。
+    /// (デバッグ専用) HUM にパスさせ、現在の盤面のまま COM に思考・着手させる。
+    ///
+    /// 任意の盤面に対する COM の応答を調べるための解析用で、デバッグビルドでのみ使える。
+    /// HUM の指し手の代わりに null move で手番を進め、進行度は HUM が着手した場合と同様に更新する。
+    /// HUM の指し手がないので、HUM の指し手の移動先に依存する定跡処理は行われない。
+    ///
+    /// HUM と COM が交互に指すという前提を破るため、以後 `game_moves()` や `kif_record()` は
+    /// 正しい棋譜にならず、この呼び出しより前に遡って `undo_step()` することもできない。
+    ///
+    /// COM の応答を返す。応答が `EngineResponse::Move` 以外なら終局となる。
+    /// 応答を `undo_step()` に渡すと COM の着手のみが戻され、null move は戻されない。
+    /// HUM が王手をかけられている場合、パスは許されないのでエラーを返す。
+    #[cfg(debug_assertions)]
+    pub fn force_com_turn(&mut self) -> anyhow::Result<EngineResponse> {
+        ensure!(!self.terminal, "game is already over");
+        ensure!(self.pos.side_to_move() == HUM, "not HUM's turn");
+        ensure!(!self.pos.is_checked(HUM), "HUM is in check");
+
+        self.pos.do_null_move();
+        self.update_progress_hum();
+
+        self.step_com()
+    }
+
+    /// COM の手番の局面で、HUM の指し手なしに COM に思考・着手させる。
+    ///
+    /// COM が先に指す手合割の初手や、`force_com_turn()` で使われる。
+    /// HUM の指し手がないので、HUM の指し手の移動先に依存する定跡処理は行われず、進行度も COM の着手分しか進まない。
+    ///
+    /// `do_step()` と同様に COM の応答を返す。応答が `EngineResponse::Move` 以外なら終局となる。
+    /// 応答は HUM の指し手を含まず、`undo_step()` に渡すと COM の着手のみが戻される。
+    /// HUM の手番で呼んだ場合、または終局後に呼んだ場合、エラーを返す。
+    pub fn step_com(&mut self) -> anyhow::Result<EngineResponse> {
+        ensure!(!self.terminal, "game is already over");
+        ensure!(self.pos.side_to_move() == COM, "not COM's turn");
+
+        let undo_info = self.make_undo_info(None);
+
+        let resp_raw = self.think(None);
+
+        Ok(self.respond(resp_raw, undo_info))
+    }
+
+    /// `do_step()` または `step_com()` を undo し、元の状態を復元する。
     pub fn undo_step(&mut self, resp: &EngineResponse) {
         // 応答が COM の指し手を含むならそれを undo する。
         if let Some(umv_com) = resp.move_com() {
             debug_assert_eq!(self.pos.side_to_move(), HUM);
             self.pos.undo_move(umv_com);
+            self.game_moves.pop();
         }
 
-        // HUM の指し手を undo し、全ての状態を復元する。
+        // HUM の指し手があればそれを undo し、全ての状態を復元する。
         debug_assert_eq!(self.pos.side_to_move(), COM);
         let undo_info = resp.undo_info();
-        self.pos.undo_move(undo_info.umv_hum);
+        if let Some(umv_hum) = undo_info.umv_hum {
+            self.pos.undo_move(umv_hum);
+            self.game_moves.pop();
+        }
         self.progress_ply = undo_info.progress_ply;
         self.progress_level = undo_info.progress_level;
         self.progress_level_sub = undo_info.progress_level_sub;
         self.book_state = undo_info.book_state;
         self.naitou_best_src_value = undo_info.naitou_best_src_value;
+        self.terminal = false;
     }
 
     /// HUM 側の指し手で局面を進め、内部状態を更新し、`EngineUndoInfo` を返す。
@@ -1589,19 +2684,37 @@ impl Engine {
         let umv_hum = self.pos.do_move(mv);
 
         // mv が自殺手だった場合、局面を元に戻してエラーを返す。
-        if self.pos.is_checked(HUM) {
+        // ただし原作の王手放置を再現する場合は自殺手も受け付ける。
+        if !self.emulate_book_check_bug && self.pos.is_checked(HUM) {
             self.pos.undo_move(umv_hum);
             bail!("suicide move");
         }
+        self.game_moves.push(umv_hum);
 
         // undo 用情報を取得しておく。
-        let progress_ply = self.progress_ply;
-        let progress_level = self.progress_level;
-        let progress_level_sub = self.progress_level_sub;
-        let book_state = self.book_state;
-        let naitou_best_src_value = self.naitou_best_src_value;
+        let undo_info = self.make_undo_info(Some(umv_hum));
+
+        self.update_progress_hum();
+
+        Ok(undo_info)
+    }
+
+    /// 現在の状態から undo 用情報を作る。`umv_hum` は直前の HUM 側の指し手(なければ `None`)。
+    #[inline]
+    fn make_undo_info(&self, umv_hum: Option<UndoableMove>) -> EngineUndoInfo {
+        EngineUndoInfo {
+            umv_hum,
+            progress_ply: self.progress_ply,
+            progress_level: self.progress_level,
+            progress_level_sub: self.progress_level_sub,
+            book_state: self.book_state,
+            naitou_best_src_value: self.naitou_best_src_value,
+        }
+    }
 
-        // 進行度更新。
+    /// HUM 側が着手した後の進行度更新。
+    #[inline]
+    fn update_progress_hum(&mut self) {
         self.increment_progress_ply();
         if self.progress_ply >= 51 {
             self.progress_level = (self.progress_level + 1).min(2);
@@ -1609,15 +2722,36 @@ impl Engine {
         if self.progress_ply >= 71 {
             self.progress_level = 3;
         }
+    }
 
-        Ok(EngineUndoInfo {
-            umv_hum,
-            progress_ply,
-            progress_level,
-            progress_level_sub,
-            book_state,
-            naitou_best_src_value,
-        })
+    /// 現在の局面が対局中に現れた回数(現在の局面自身を含む)を返す。
+    ///
+    /// 局面を複製し、不可逆な指し手に当たるまで `game_moves` を遡って数える。
+    /// `force_com_turn()` による null move より前には遡らない。
+    fn repetition_count(&self) -> u32 {
+        let signature = self.pos.signature();
+
+        let mut pos = self.pos.clone();
+        let mut count = 1;
+        for &umv in self.game_moves.iter().rev() {
+            if !umv.is_reversible() {
+                break;
+            }
+            // null move を挟んでいる場合、指し手と局面が整合しなくなるのでそこで打ち切る。
+            let pc = umv.piece_src();
+            if pc.side() == pos.side_to_move()
+                || pos.board()[umv.dst()] != pc
+                || pos.board()[umv.src()] != NO_PIECE
+            {
+                break;
+            }
+            pos.undo_move(umv);
+            if pos.signature() == signature {
+                count += 1;
+            }
+        }
+
+        count
     }
 
     /// COM 側の指し手で局面を進め、内部状態を更新し、`UndoableMove` を返す。
@@ -1627,6 +2761,7 @@ impl Engine {
 
         // COM 側の着手を行い、UndoableMove を取得。
         let umv_com = self.pos.do_move(mv);
+        self.game_moves.push(umv_com);
 
         // 進行度更新。COM 側は単に手数をインクリメントするだけ。
         self.increment_progress_ply();
@@ -1640,3 +2775,1105 @@ impl Engine {
         self.progress_ply = (self.progress_ply + 1).min(100);
     }
 }
+
+#[cfg(test)]
+impl Engine {
+    /// `do_step(mv_hum)` -> `undo_step()` -> `do_step(mv_hum)` を行い、
+    /// undo により `do_step()` 前の状態が、再度の `do_step()` により 1 回目と同じ状態が復元されることを確認する。
+    ///
+    /// 終了時、`self` は `do_step(mv_hum)` を行った状態になる。
+    fn verify_undo_roundtrip(&mut self, mv_hum: Move) -> anyhow::Result<()> {
+        let before = self.clone();
+
+        let resp = self.do_step(mv_hum)?;
+        let after = self.clone();
+
+        self.undo_step(&resp);
+        self.ensure_same_state(&before, "after undo_step()")?;
+
+        let resp_redo = self.do_step(mv_hum)?;
+        ensure!(
+            std::mem::discriminant(&resp) == std::mem::discriminant(&resp_redo)
+                && resp.move_com() == resp_redo.move_com(),
+            "response mismatch: {:?} vs {:?}",
+            resp,
+            resp_redo
+        );
+        self.ensure_same_state(&after, "after redoing do_step()")?;
+
+        Ok(())
+    }
+
+    /// `self` と `other` の状態(局面、進行度、定跡状態など)が等しいことを確認する。
+    fn ensure_same_state(&self, other: &Self, when: &str) -> anyhow::Result<()> {
+        ensure!(
+            self.pos.same_position(&other.pos) && self.pos.ply() == other.pos.ply(),
+            "{}: position mismatch",
+            when
+        );
+        ensure!(
+            (
+                self.progress_ply,
+                self.progress_level,
+                self.progress_level_sub
+            ) == (
+                other.progress_ply,
+                other.progress_level,
+                other.progress_level_sub
+            ),
+            "{}: progress mismatch",
+            when
+        );
+        ensure!(
+            self.book_state == other.book_state,
+            "{}: book state mismatch",
+            when
+        );
+        ensure!(
+            self.naitou_best_src_value == other.naitou_best_src_value,
+            "{}: naitou_best_src_value mismatch",
+            when
+        );
+        ensure!(
+            self.game_moves == other.game_moves,
+            "{}: game moves mismatch",
+            when
+        );
+        ensure!(
+            self.terminal == other.terminal,
+            "{}: terminal flag mismatch",
+            when
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    use crate::sfen::{sfen_decode_move, sfen_decode_position};
+
+    use super::*;
+
+    fn mv(s: &str) -> Move {
+        sfen_decode_move(s).unwrap()
+    }
+
+    /// HUM の手番の局面を指定して思考エンジンを作る。進行度などは初期状態とする。
+    fn engine_from_sfen(sfen: &str) -> Engine {
+        let (side_to_move, board, hands) = sfen_decode_position(sfen).unwrap();
+        assert_eq!(side_to_move, HUM);
+
+        let (mut engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+        engine.pos = Position::new(side_to_move, board, hands);
+
+        engine
+    }
+
+    #[test]
+    fn test_resign_threshold() {
+        let (mut engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+        assert_eq!(
+            engine.resign_threshold(),
+            Some(Engine::RESIGN_THRESHOLD_DEFAULT)
+        );
+
+        // 閾値 0 なら必ず投了する。
+        engine.set_resign_threshold(Some(0));
+        let resp = engine.do_step(mv("7g7f")).unwrap();
+        assert!(matches!(resp, EngineResponse::HumWin(_)));
+        engine.undo_step(&resp);
+
+        // 投了しない設定なら同じ局面で指し手を返す。
+        engine.set_resign_threshold(None);
+        let resp = engine.do_step(mv("7g7f")).unwrap();
+        assert!(matches!(resp, EngineResponse::Move(_)));
+    }
+
+    #[test]
+    fn test_naitou_best_src_value_carryover() {
+        // COM 玉は合駒(金打ち)でしか王手を防げない。
+        let mut engine = engine_from_sfen("sfen 3lkl3/3p1p3/9/9/9/9/9/9/4K4 b Rg 1");
+        assert_eq!(engine.naitou_best_src_value(), 0);
+
+        let resp = engine.do_step(mv("R*5e")).unwrap();
+        let umv_com = resp.move_com().unwrap();
+        assert!(umv_com.is_drop());
+        assert_eq!(umv_com.dropped_piece_kind(), GOLD);
+
+        // 最善手の値は次の局面に持ち越される。
+        assert_eq!(
+            engine.naitou_best_src_value(),
+            naitou_com_drop_src_value(GOLD)
+        );
+
+        // 持ち越された値は、次の局面で暫定最善手と評価が同じ合駒の比較に影響する。
+        // 初期化されていれば銀打ちは採用されないが、金打ちの値が残っていると「より安い駒」として採用される。
+        let fresh = engine_from_sfen("sfen 3lkl3/3p1p3/9/9/9/9/9/9/4K4 b Rg 1");
+        let mut root_eval = engine.evaluate_root();
+        root_eval.disadv_price = 40;
+        let leaf_eval = LeafEvaluation::new();
+        let umv_silver = UndoableMove::from_move_drop(Move::new_drop(SILVER, SQ_55));
+        assert_eq!(
+            fresh.compare_with_best(&root_eval, &leaf_eval, &leaf_eval, umv_silver),
+            (false, "drop src value")
+        );
+        assert_eq!(
+            engine.compare_with_best(&root_eval, &leaf_eval, &leaf_eval, umv_silver),
+            (true, "drop src value")
+        );
+
+        // undo すれば元に戻る。
+        engine.undo_step(&resp);
+        assert_eq!(engine.naitou_best_src_value(), 0);
+    }
+
+    #[test]
+    fn test_last_think_stats() {
+        // COM の候補手は玉 1 手、歩 2 手、金打ち 74 手。
+        // 王手がかかっているので駒捨ては却下されない。
+        let mut engine = engine_from_sfen("sfen 3lkl3/3p1p3/9/9/9/9/9/9/4K4 b Rg 1");
+        engine.do_step(mv("R*5e")).unwrap();
+        assert_eq!(
+            engine.last_think_stats(),
+            ThinkStats {
+                generated_count: 77,
+                rejected_by_sacrifice_count: 0,
+                rejected_by_drop_pawn_mate_count: 0,
+                evaluated_count: 77,
+                book_move_adopted: false,
+                book_skip_reason: Some(BookSkipReason::NotQuiet),
+            }
+        );
+
+        // 平手初期局面から角道を開けると、４四歩などは駒捨てとして却下される。
+        let (mut engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+        engine.do_step(mv("7g7f")).unwrap();
+        let stats = engine.last_think_stats();
+        assert_eq!(stats.generated_count, 30);
+        assert!(stats.rejected_by_sacrifice_count > 0);
+        assert_eq!(stats.rejected_by_drop_pawn_mate_count, 0);
+        assert_eq!(
+            stats.evaluated_count + stats.rejected_by_sacrifice_count,
+            30
+        );
+        assert!(stats.book_move_adopted);
+    }
+
+    #[test]
+    fn test_book_move_query() {
+        // HUM の角道が開いている。
+        const SFEN: &str = "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL b - 1";
+
+        let engine = engine_from_sfen(SFEN);
+
+        assert!(engine.book_move_is_legal(mv("3c3d")));
+        assert!(engine.book_move_is_legal(mv("4c4d")));
+        assert!(!engine.book_move_is_legal(mv("3d3e"))); // 移動元に駒がない
+        assert!(!engine.book_move_is_legal(mv("7g7f"))); // HUM の駒
+        assert!(!engine.book_move_is_legal(mv("2b3c"))); // 移動先に COM の駒
+        assert!(!engine.book_move_is_legal(mv("P*5e"))); // 駒打ち
+
+        // HUM が９六歩と指した局面で、３四歩は駒損しないが、４四歩はタダで取られる。
+        assert_eq!(engine.book_move_disadv(mv("9g9f"), mv("3c3d")).unwrap(), 0);
+        assert_eq!(
+            engine.book_move_disadv(mv("9g9f"), mv("4c4d")).unwrap(),
+            naitou_piece_price_d(PAWN)
+        );
+        assert!(engine.book_move_disadv(mv("9g9f"), mv("3d3e")).is_err());
+
+        // 局面は変化しない。
+        assert_eq!(engine.position().side_to_move(), HUM);
+        assert!(engine.position().same_position(&engine_from_sfen(SFEN).pos));
+    }
+
+    #[test]
+    fn test_response_evaluation() {
+        const SFEN: &str = "sfen 3lkl3/3p1p3/9/9/9/9/9/9/4K4 b Rg 1";
+
+        let mut engine = engine_from_sfen(SFEN);
+        let resp = engine.do_step(mv("R*5e")).unwrap();
+        let (umv_com, eval) = match &resp {
+            EngineResponse::Move(res) => (res.move_com(), res.evaluation().unwrap().clone()),
+            _ => panic!("unexpected response: {:?}", resp),
+        };
+
+        // 同じ局面で最善手を単独で評価した結果と一致するはず。
+        let mut engine = engine_from_sfen(SFEN);
+        engine.do_move_hum(mv("R*5e")).unwrap();
+        let root_eval = engine.evaluate_root();
+        let umv = engine.pos.do_move(umv_com.into());
+        let mut expected = engine.evaluate_leaf(&root_eval, umv).unwrap();
+        engine.revise_leaf_evaluation(&root_eval, umv, &mut expected);
+        assert_eq!(eval, expected);
+
+        // 定跡手には評価がない。
+        let (mut engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+        let resp = engine.do_step(mv("7g7f")).unwrap();
+        assert!(engine.last_think_stats().book_move_adopted);
+        match &resp {
+            EngineResponse::Move(res) => assert!(res.evaluation().is_none()),
+            _ => panic!("unexpected response: {:?}", resp),
+        }
+    }
+
+    #[test]
+    fn test_emulate_book_check_bug() {
+        // HUM 玉が５六へ動く手は５五の歩の利きに入る自殺手。
+        const SFEN: &str = "sfen lnsgkgsnl/1r5b1/pppp1pppp/9/4p4/9/PPPPKPPPP/1B5R1/LNSG1GSNL b - 1";
+
+        let mut engine = engine_from_sfen(SFEN);
+        assert!(!engine.emulate_book_check_bug());
+        assert!(engine.do_step(mv("5g5f")).is_err());
+
+        let mut engine = engine_from_sfen(SFEN);
+        engine.set_emulate_book_check_bug(true);
+        let resp = engine.do_step(mv("5g5f")).unwrap();
+
+        // 王手放置して定跡手を指す。
+        assert_eq!(resp.move_com().map(Move::from), Some(mv("3c3d")));
+        assert!(engine.last_think_stats().book_move_adopted);
+        assert!(engine.pos.is_checked(HUM));
+
+        // 定跡処理が強制されない場合は HUM の自殺手となる。
+        let mut engine = engine_from_sfen(SFEN);
+        engine.set_emulate_book_check_bug(true);
+        engine.progress_level = 1;
+        let resp = engine.do_step(mv("5g5f")).unwrap();
+        assert!(matches!(resp, EngineResponse::HumSuicide(_)));
+    }
+
+    #[test]
+    fn test_kif_record() {
+        let (mut engine, umv_com) = Engine::new(Handicap::HumSenteSikenbisha);
+        assert!(umv_com.is_none());
+        assert!(engine.game_moves().is_empty());
+
+        let resp1 = engine.do_step(mv("7g7f")).unwrap();
+        let resp2 = engine.do_step(mv("8h2b+")).unwrap();
+
+        let mut expected = vec![
+            UndoableMove::from_move_walk(mv("7g7f"), H_PAWN, NO_PIECE),
+            resp1.move_com().unwrap(),
+            UndoableMove::from_move_walk(mv("8h2b+"), H_BISHOP, C_BISHOP),
+        ];
+        expected.extend(resp2.move_com());
+        assert_eq!(engine.game_moves(), expected);
+
+        let kif = engine.kif_record();
+        let lines: Vec<_> = kif.lines().collect();
+        assert_eq!(lines.len(), 1 + expected.len());
+        assert_eq!(lines[1], "   1 ７六歩(77)");
+        assert_eq!(lines[3], "   3 ２二角成(88)");
+
+        // undo すると指し手も取り除かれる。
+        engine.undo_step(&resp2);
+        assert_eq!(engine.game_moves(), &expected[..2]);
+
+        // COM が先に指す手合割では COM の初手を含む。
+        let (engine, umv_com) = Engine::new(Handicap::ComSenteSikenbisha);
+        assert_eq!(engine.game_moves(), [umv_com.unwrap()]);
+    }
+
+    #[test]
+    fn test_response_quiet_and_force_skip_book() {
+        // 定跡を使わないようにして、探索結果がそのまま返されるようにする。
+        let (mut engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+        engine.progress_level = 1;
+        let resp = engine.do_step(mv("2g2f")).unwrap();
+        match &resp {
+            EngineResponse::Move(res) => {
+                assert!(res.evaluation().is_some());
+                assert!(res.was_quiet());
+                assert!(!res.forced_skip_book());
+            }
+            _ => panic!("unexpected response: {:?}", resp),
+        }
+
+        // 角を取り返す手は駒取りなので quiet でない。
+        let mut engine = engine_from_sfen(
+            "sfen lnsgkgsnl/1r5b1/pppppp1pp/6p2/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL b - 1",
+        );
+        engine.progress_level = 1;
+        let resp = engine.do_step(mv("8h2b+")).unwrap();
+        match &resp {
+            EngineResponse::Move(res) => {
+                assert_eq!(res.move_com().piece_captured(), H_HORSE);
+                assert!(!res.was_quiet());
+                assert!(!res.forced_skip_book());
+            }
+            _ => panic!("unexpected response: {:?}", resp),
+        }
+    }
+
+    #[test]
+    fn test_terminal_and_reset_to_root() {
+        let (mut engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+        assert!(!engine.is_terminal());
+
+        // 閾値 0 なら必ず投了し、終局となる。終局後の do_step() はエラー。
+        engine.set_resign_threshold(Some(0));
+        let resp = engine.do_step(mv("7g7f")).unwrap();
+        assert!(matches!(resp, EngineResponse::HumWin(_)));
+        assert!(engine.is_terminal());
+        assert!(engine.do_step(mv("2g2f")).is_err());
+
+        // undo すれば終局状態も戻る。
+        engine.undo_step(&resp);
+        assert!(!engine.is_terminal());
+
+        engine.set_resign_threshold(None);
+        engine.do_step(mv("7g7f")).unwrap();
+        engine.do_step(mv("2g2f")).unwrap();
+        assert!(engine.progress_ply() > 0);
+
+        // 開始局面に戻すと、新規に作ったものと同じ状態になる。設定は保持される。
+        assert_eq!(engine.reset_to_root(), None);
+        let (fresh, _) = Engine::new(Handicap::HumSenteSikenbisha);
+        assert!(engine.position().same_position(fresh.position()));
+        assert_eq!(engine.position().ply(), fresh.position().ply());
+        assert_eq!(engine.progress_ply(), fresh.progress_ply());
+        assert_eq!(engine.progress_level(), fresh.progress_level());
+        assert!(engine.game_moves().is_empty());
+        assert!(!engine.is_terminal());
+        assert_eq!(engine.resign_threshold(), None);
+
+        // COM が先に指す手合割では COM の初手も再び指される。
+        let (mut engine, umv_first) = Engine::new(Handicap::ComHishaochi);
+        let resp = engine.do_step(mv("7g7f")).unwrap();
+        assert!(matches!(resp, EngineResponse::Move(_)));
+        assert_eq!(engine.reset_to_root(), umv_first);
+        let (fresh, _) = Engine::new(Handicap::ComHishaochi);
+        assert!(engine.position().same_position(fresh.position()));
+        assert_eq!(engine.game_moves(), fresh.game_moves());
+
+        // 設定は COM の初手にも適用される。閾値 0 なら初手で投了する。
+        engine.set_resign_threshold(Some(0));
+        assert_eq!(engine.reset_to_root(), None);
+        assert!(engine.is_terminal());
+        assert_eq!(engine.position().side_to_move(), COM);
+    }
+
+    #[test]
+    fn test_verify_undo_roundtrip() {
+        // 定跡を抜けて通常の思考に入るまで進める。
+        let (mut engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+        for s in [
+            "7g7f", "2g2f", "2f2e", "6i7h", "8h7g", "7i6h", "5g5f", "2e2d", "2h2d",
+        ] {
+            engine.verify_undo_roundtrip(mv(s)).unwrap();
+        }
+
+        // 終局する応答でも同様。
+        engine.set_resign_threshold(Some(0));
+        engine.verify_undo_roundtrip(mv("1g1f")).unwrap();
+        assert!(engine.is_terminal());
+
+        // COM が先に指す手合割。
+        let (mut engine, _) = Engine::new(Handicap::ComNimaiochi);
+        for s in ["7g7f", "2g2f", "2f2e"] {
+            engine.verify_undo_roundtrip(mv(s)).unwrap();
+        }
+
+        // 自殺手はエラーとなる。
+        let mut engine = engine_from_sfen("sfen 4k4/9/9/9/4r4/9/9/9/4K4 b - 1");
+        assert!(engine.verify_undo_roundtrip(mv("5i4i")).is_ok());
+        let mut engine = engine_from_sfen("sfen 4k4/9/9/9/4r4/9/9/9/4K4 b - 1");
+        assert!(engine.verify_undo_roundtrip(mv("5i5h")).is_err());
+    }
+
+    #[test]
+    fn test_would_suppress_check() {
+        // COM は手駒がなく寄せが見込めないので、飛車で王手をかける手は評価を下げられる。
+        let engine = engine_from_sfen("sfen 4k3r/9/9/9/9/9/9/9/4K4 b - 1");
+        assert!(engine.would_suppress_check(mv("5i4i"), mv("1a4a")).unwrap());
+        assert!(engine
+            .would_suppress_check(mv("5i4i"), mv("1a1i+"))
+            .unwrap());
+
+        // 王手でない手は対象外。
+        assert!(!engine.would_suppress_check(mv("5i4i"), mv("1a2a")).unwrap());
+
+        // 思考エンジンの状態は変わらない。
+        assert_eq!(engine.pos.side_to_move(), HUM);
+        assert!(engine.game_moves().is_empty());
+
+        // COM の手駒が多い(power_com >= 35)なら王手の評価は下げられない。
+        let engine = engine_from_sfen("sfen 4k3r/9/9/9/9/9/9/9/4K4 b 2b4g3p 1");
+        assert!(!engine.would_suppress_check(mv("5i4i"), mv("1a4a")).unwrap());
+
+        // 自殺手はエラー。
+        let engine = engine_from_sfen("sfen 4k4/9/9/9/9/9/9/9/r3K4 b - 1");
+        assert!(engine.would_suppress_check(mv("5i4i"), mv("9i9h")).is_err());
+    }
+
+    #[test]
+    fn test_debug_compare() {
+        let engine = engine_from_sfen("sfen 4k3r/9/9/9/9/9/9/9/4K4 b - 1");
+
+        // 評価値は同じで、王手をかける手の方が adv_price が大きい。
+        let out = engine
+            .debug_compare(mv("5i4i"), mv("1a2a"), mv("1a4a"))
+            .unwrap();
+        assert!(out.contains("b vs best a: improved (decided by adv_price)"));
+        assert!(out.contains("a vs best b: not improved (decided by adv_price)"));
+
+        // 成る手の方が成駒が多い。
+        let out = engine
+            .debug_compare(mv("5i4i"), mv("1a1i+"), mv("1a1b"))
+            .unwrap();
+        assert!(out.contains("b vs best a: not improved (decided by com_promo_count)"));
+        assert!(out.contains("a vs best b: improved (decided by com_promo_count)"));
+
+        // 思考エンジンの状態は変わらない。
+        assert_eq!(engine.pos.side_to_move(), HUM);
+        assert!(engine.game_moves().is_empty());
+    }
+
+    #[test]
+    fn test_response_source() {
+        // 序盤は定跡手。
+        let (mut engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+        let resp = engine.do_step(mv("7g7f")).unwrap();
+        match &resp {
+            EngineResponse::Move(res) => assert_eq!(res.source(), MoveSource::Book),
+            _ => panic!("unexpected response: {:?}", resp),
+        }
+
+        // 王手に対して合駒する手は探索による。
+        let mut engine = engine_from_sfen("sfen 3lkl3/3p1p3/9/9/9/9/9/9/4K4 b Rg 1");
+        let resp = engine.do_step(mv("R*5e")).unwrap();
+        match &resp {
+            EngineResponse::Move(res) => {
+                assert!(res.move_com().is_drop());
+                assert_eq!(res.source(), MoveSource::Search);
+            }
+            _ => panic!("unexpected response: {:?}", resp),
+        }
+    }
+
+    #[test]
+    fn test_effect_overlay() {
+        let (mut engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+        engine.do_step(mv("7g7f")).unwrap();
+
+        let (hum, com) = engine.effect_overlay();
+        assert_eq!(&hum, engine.position().effect_count_board(HUM));
+        assert_eq!(&com, engine.position().effect_count_board(COM));
+        assert!(hum[SQ_75] > 0); // ７六の歩の利き
+    }
+
+    #[test]
+    fn test_effect_overlay_after() {
+        let (engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+        let before = engine.effect_overlay();
+
+        let (hum, com) = engine.effect_overlay_after(mv("7g7f"), mv("3c3d")).unwrap();
+
+        // 指し手を適用した局面を一から作った場合の利きと一致する。
+        let mut pos = engine.position().clone();
+        pos.do_move(mv("7g7f"));
+        pos.do_move(mv("3c3d"));
+        let pos = Position::new(pos.side_to_move(), pos.board().clone(), *pos.hands());
+        assert_eq!(&hum, pos.effect_count_board(HUM));
+        assert_eq!(&com, pos.effect_count_board(COM));
+        assert!(com[SQ_35] > 0); // ３四の歩の利き
+
+        // 局面は元に戻っている。
+        assert_eq!(engine.effect_overlay(), before);
+        assert_eq!(engine.position().side_to_move(), HUM);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn test_force_com_turn() {
+        // 通常の手順で到達した局面と、同じ盤面から HUM にパスさせた場合とで COM の指し手が一致する。
+        const CASES: &[(&str, &str, &str)] = &[
+            (
+                "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+                "7g7f",
+                "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL b - 1",
+            ),
+            (
+                "sfen 3lkl3/3p1p3/9/9/9/9/9/9/4K4 b Rg 1",
+                "R*5e",
+                "sfen 3lkl3/3p1p3/9/9/4R4/9/9/9/4K4 b g 1",
+            ),
+        ];
+
+        for &(sfen_before, mv_hum, sfen_after) in CASES {
+            let mut engine = engine_from_sfen(sfen_before);
+            let expected = engine.do_step(mv(mv_hum)).unwrap().move_com().unwrap();
+
+            let mut engine = engine_from_sfen(sfen_after);
+            let actual = engine.force_com_turn().unwrap().move_com().unwrap();
+
+            assert_eq!(actual, expected);
+            assert_eq!(engine.position().side_to_move(), HUM);
+            assert_eq!(engine.progress_ply(), 2);
+        }
+
+        // HUM が王手をかけられている局面ではパスできない。
+        let mut engine = engine_from_sfen("sfen 4k4/9/9/9/9/9/9/4r4/4K4 b G 1");
+        let before = engine.clone();
+        assert!(engine.force_com_turn().is_err());
+        assert_eq!(engine.position().side_to_move(), HUM);
+        assert_eq!(engine.position().ply(), before.position().ply());
+        assert_eq!(engine.progress_ply(), before.progress_ply());
+    }
+
+    #[test]
+    fn test_do_step_traced() {
+        // 探索による指し手。
+        let sfen = "sfen 3lkl3/3p1p3/9/9/9/9/9/9/4K4 b Rg 1";
+        let mut engine = engine_from_sfen(sfen);
+        let mut engine_untraced = engine.clone();
+
+        let (resp, trace) = engine.do_step_traced(mv("R*5e")).unwrap();
+        let resp_untraced = engine_untraced.do_step(mv("R*5e")).unwrap();
+        assert_eq!(resp.move_com(), resp_untraced.move_com());
+
+        // 候補手は生成順に全て記録される。
+        let mut pos = engine_from_sfen(sfen).position().clone();
+        pos.do_move(mv("R*5e"));
+        let mvs: Vec<_> = generate_moves_com(&pos).into_iter().collect();
+        let cand_mvs: Vec<_> = trace.candidates.iter().map(|cand| cand.mv).collect();
+        assert_eq!(cand_mvs, mvs);
+        assert!(trace.root_eval.is_some());
+        assert!(trace.book.is_empty());
+
+        // 最後に採用された候補手が最善手。
+        let best = trace
+            .candidates
+            .iter()
+            .rev()
+            .find(|cand| cand.improved)
+            .unwrap();
+        assert_eq!(best.mv, Move::from(resp.move_com().unwrap()));
+        match &resp_untraced {
+            EngineResponse::Move(res) => assert_eq!(best.revised_eval.as_ref(), res.evaluation()),
+            _ => panic!("unexpected response: {:?}", resp_untraced),
+        }
+        assert!(trace
+            .candidates
+            .iter()
+            .all(|cand| cand.initial_eval.is_some() == cand.revised_eval.is_some()));
+
+        // 定跡手。
+        let (mut engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+        let (resp, trace) = engine.do_step_traced(mv("7g7f")).unwrap();
+        let accepted = trace.book.last().unwrap();
+        assert_eq!(accepted.decision, BookDecision::Accepted);
+        assert_eq!(accepted.mv, Move::from(resp.move_com().unwrap()));
+
+        // 記録は do_step_traced() の間のみ行われる。
+        assert!(engine.trace.is_none());
+    }
+
+    #[test]
+    fn test_classify_hum_move() {
+        // 自殺手。
+        let mut engine = engine_from_sfen("sfen 3rk4/9/9/9/9/9/9/9/4K4 b P 1");
+        let sfen_orig = engine.position().to_sfen();
+        assert_eq!(engine.classify_hum_move(mv("5i5h")), HumMoveClass::Legal);
+        assert_eq!(engine.classify_hum_move(mv("5i6i")), HumMoveClass::Suicide);
+        assert!(engine.do_step(mv("5i6i")).is_err());
+        assert_eq!(engine.position().to_sfen(), sfen_orig);
+
+        // 原作の王手放置を再現する場合、自殺手も受け付けられる。
+        engine.set_emulate_book_check_bug(true);
+        assert_eq!(
+            engine.classify_hum_move(mv("5i6i")),
+            HumMoveClass::PseudoLegalButUnusual
+        );
+
+        // HUM 側の打ち歩詰め。
+        let mut engine = engine_from_sfen("sfen 7nk/9/7G1/9/9/9/9/9/4K4 b P 1");
+        assert_eq!(engine.classify_hum_move(mv("P*1d")), HumMoveClass::Legal);
+        assert_eq!(
+            engine.classify_hum_move(mv("P*1b")),
+            HumMoveClass::PseudoLegalButUnusual
+        );
+        assert!(engine.do_step(mv("P*1b")).is_ok());
+    }
+
+    #[test]
+    fn test_is_position_quiet() {
+        // 序盤の局面は quiet。定跡処理を避けるため進行度を進めておく。
+        let mut engine = engine_from_sfen("startpos");
+        engine.progress_level = 1;
+        let resp = engine.do_step(mv("7g7f")).unwrap();
+        let resp = match resp {
+            EngineResponse::Move(resp) => resp,
+            _ => panic!("unexpected response: {:?}", resp),
+        };
+        assert_eq!(resp.source(), MoveSource::Search);
+        assert_eq!(resp.move_com().piece_captured(), NO_PIECE);
+
+        let mut engine = engine_from_sfen(
+            "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL b - 1",
+        );
+        engine.progress_level = 1;
+        assert!(engine.is_position_quiet());
+        assert_eq!(engine.is_position_quiet(), resp.was_quiet());
+
+        // COM の銀がただで取られる局面は quiet でない。
+        let engine = engine_from_sfen("sfen 4k4/9/9/9/4s4/9/9/4R4/4K4 b - 1");
+        assert!(!engine.is_position_quiet());
+    }
+
+    #[test]
+    fn test_opening_exit_threshold() {
+        // 後半の 2 手に対する COM の応答は quiet でない。
+        let mvs = ["7g7f", "2g2f", "2f2e", "2e2d", "2h2d"];
+
+        let (mut engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+        assert_eq!(
+            engine.opening_exit_threshold(),
+            Engine::OPENING_EXIT_THRESHOLD_DEFAULT
+        );
+        for s in mvs {
+            engine.do_step(mv(s)).unwrap();
+        }
+        assert_eq!(engine.progress_level_sub(), 2);
+        assert_eq!(engine.progress_level(), 0);
+
+        // 閾値を 2 に下げると序盤を抜ける。
+        let (mut engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+        engine.set_opening_exit_threshold(2);
+        for s in mvs {
+            engine.do_step(mv(s)).unwrap();
+        }
+        assert_eq!(engine.progress_level_sub(), 2);
+        assert_eq!(engine.progress_level(), 1);
+
+        // 設定は reset_to_root() 後も保持される。
+        engine.reset_to_root();
+        assert_eq!(engine.opening_exit_threshold(), 2);
+    }
+
+    #[test]
+    fn test_captured_by() {
+        let (engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+        assert!(engine.captured_by(HUM).is_empty());
+        assert!(engine.captured_by(COM).is_empty());
+
+        let mut engine = engine_from_sfen("sfen 4k4/9/9/9/4s4/9/9/4R4/4K4 b - 1");
+        let resp = engine.do_step(mv("5h5e")).unwrap();
+        assert_eq!(engine.captured_by(HUM)[SILVER], 1);
+        assert_eq!(engine.hands(), engine.position().hands());
+        assert_eq!(engine.captured_by(HUM), engine.hands()[HUM]);
+
+        engine.undo_step(&resp);
+        assert!(engine.captured_by(HUM).is_empty());
+    }
+
+    #[test]
+    fn test_step_com() {
+        // HUM の手番では呼べない。
+        let (mut engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+        assert!(engine.step_com().is_err());
+
+        // COM の手番の局面から COM の指し手 1 手だけ進める。
+        let (side_to_move, board, hands) =
+            sfen_decode_position("sfen 3lkl3/3p1p3/9/9/4R4/9/9/9/4K4 w g 1").unwrap();
+        engine.pos = Position::new(side_to_move, board, hands);
+        let progress_ply = engine.progress_ply();
+
+        let pos_before = engine.position().clone();
+        let resp = engine.step_com().unwrap();
+        assert!(matches!(resp, EngineResponse::Move(_)));
+        let umv = resp.move_com().unwrap();
+        assert!(umv.is_drop());
+        assert_eq!(engine.position().side_to_move(), HUM);
+        assert_eq!(engine.progress_ply(), progress_ply + 1);
+        assert_eq!(engine.game_moves().last(), Some(&umv));
+        assert!(!engine.is_terminal());
+
+        // 続けては呼べない。
+        assert!(engine.step_com().is_err());
+
+        // undo_step() で COM の着手のみ戻せる。
+        engine.undo_step(&resp);
+        assert!(engine.position().same_position(&pos_before));
+        assert_eq!(engine.progress_ply(), progress_ply);
+    }
+
+    #[test]
+    fn test_step_com_com_win() {
+        // COM は金打ちで HUM 玉を詰ませる。
+        let (mut engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+        let (side_to_move, board, hands) =
+            sfen_decode_position("sfen 4k4/9/9/9/9/9/4p4/9/4K4 w g 1").unwrap();
+        engine.pos = Position::new(side_to_move, board, hands);
+
+        let resp = engine.step_com().unwrap();
+        assert!(matches!(resp, EngineResponse::ComWin(_)));
+        assert_eq!(
+            resp.move_com().map(|umv| umv.to_usi()),
+            Some("G*5h".to_owned())
+        );
+        assert!(engine.is_terminal());
+
+        // 終局後は進められない。
+        assert_eq!(engine.classify_hum_move(mv("5i4i")), HumMoveClass::GameOver);
+        assert!(engine.do_step(mv("5i4i")).is_err());
+        assert!(engine.effect_overlay_after(mv("5i4i"), mv("5g5h")).is_err());
+        assert!(engine.candidate_is_suicide(mv("5i4i"), mv("5g5h")).is_err());
+        assert!(engine.promoted_walk_bonus(mv("5i4i"), mv("5g5h")).is_err());
+        assert!(engine.book_move_disadv(mv("5i4i"), mv("5g5h")).is_err());
+    }
+
+    #[test]
+    fn test_is_rejected_as_sacrifice() {
+        let engine = engine_from_sfen("sfen 4k4/9/9/9/9/9/9/9/4K4 b s 1");
+
+        // 王手になる銀打ちでも、ただで取られるなら駒捨てとして却下される。
+        assert!(engine
+            .is_rejected_as_sacrifice(mv("5i4i"), mv("S*5h"))
+            .unwrap());
+
+        // HUM の利きがないマスへの駒打ちは駒捨てではない。
+        assert!(!engine
+            .is_rejected_as_sacrifice(mv("5i4i"), mv("S*5e"))
+            .unwrap());
+
+        // 思考エンジンの状態は変わらない。
+        assert_eq!(engine.position().side_to_move(), HUM);
+        assert_eq!(engine.last_think_stats(), ThinkStats::default());
+    }
+
+    #[test]
+    fn test_rank_hum_moves() {
+        let engine = engine_from_sfen(
+            "sfen lnsgk2nl/1r4gs1/p1pppp1pp/1p4p2/7P1/2P6/PP1PPPP1P/1B5R1/LNSGKGSNL b Bb 1",
+        );
+
+        let ranking = engine.rank_hum_moves();
+        assert!(!ranking.is_empty());
+
+        // 先頭要素は、反転局面で COM が探索により選ぶ手と一致する。
+        let mut flipped = engine.clone();
+        flipped.pos = engine.pos.flipped();
+        let root_eval = flipped.evaluate_root();
+        match flipped.think_search(&root_eval) {
+            EngineResponseRaw::Move(resp) => assert_eq!(resp.best_mv.rotate180(), ranking[0].0),
+            _ => panic!("search should return a move"),
+        }
+
+        // 全て HUM の合法手であり、重複しない。
+        let mut pos = engine.position().clone();
+        let legal_mvs = pos.legal_moves();
+        for (i, (mv, _)) in ranking.iter().enumerate() {
+            assert!(legal_mvs.contains(mv));
+            assert!(!ranking[..i].iter().any(|(other, _)| other == mv));
+        }
+
+        // 思考エンジンの状態は変わらない。
+        assert!(engine.position().same_position(&pos));
+        assert_eq!(engine.position().side_to_move(), HUM);
+    }
+
+    #[test]
+    fn test_last_book_skip_reason() {
+        // COM は桂打ちで飛車と角を両取りできる。
+        let mut engine = engine_from_sfen("sfen 4k4/9/9/9/9/9/3R1B3/9/4K4 b n 1");
+
+        let resp = engine.do_step(mv("5i5h")).unwrap();
+        let resp = match resp {
+            EngineResponse::Move(resp) => resp,
+            _ => panic!("COM should return a move"),
+        };
+        assert_eq!(Move::from(resp.move_com()), mv("N*5e"));
+        assert_eq!(
+            engine.last_book_skip_reason(),
+            Some(BookSkipReason::MultipleTargets)
+        );
+    }
+
+    #[test]
+    fn test_power() {
+        let engine = engine_from_sfen("sfen 4k4/9/9/9/9/9/9/4+R4/4K4 b B2Pgs 1");
+
+        let root_eval = engine.evaluate_root();
+        assert_eq!(engine.power(HUM), root_eval.power_hum);
+        assert_eq!(engine.power(COM), root_eval.power_com);
+
+        // 持駒と成駒の多い HUM の方が戦力が大きい。
+        assert!(engine.power(HUM) > engine.power(COM));
+    }
+
+    #[test]
+    fn test_is_fourfold() {
+        // COM 玉は１一と２一を往復するしかない。HUM 玉も５九と４九を往復する。
+        let mut engine = engine_from_sfen("sfen 8k/R8/9/9/9/9/9/9/4K1L2 b - 1");
+        engine.progress_level = 1;
+
+        let mut fourfolds = vec![];
+        for mv_hum in ["5i4i", "4i5i", "5i4i", "4i5i", "5i4i", "4i5i"] {
+            match engine.do_step(mv(mv_hum)).unwrap() {
+                EngineResponse::Move(resp) => fourfolds.push(resp.is_fourfold()),
+                _ => panic!("COM should return a move"),
+            }
+        }
+
+        // 開始局面が 4 回目に現れたところで千日手となる。
+        assert_eq!(fourfolds, [false, false, false, false, false, true]);
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn test_book_rng() {
+        use rand::SeedableRng as _;
+
+        fn first_com_move(seed: Option<u64>) -> UndoableMove {
+            let (mut engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+            engine.set_book_rng(seed.map(StdRng::seed_from_u64));
+            match engine.do_step(mv("7g7f")).unwrap() {
+                EngineResponse::Move(resp) => {
+                    assert_eq!(resp.source(), MoveSource::Book);
+                    resp.move_com()
+                }
+                _ => panic!("COM should return a move"),
+            }
+        }
+
+        let default_mv = first_com_move(None);
+
+        // 同じシードなら同じ定跡手が選ばれる。
+        for seed in 0..10 {
+            assert_eq!(first_com_move(Some(seed)), first_com_move(Some(seed)));
+        }
+
+        // いずれかのシードでは既定とは異なる定跡手が選ばれる。
+        let seed = (0..10)
+            .find(|&seed| first_com_move(Some(seed)) != default_mv)
+            .expect("some seed should choose a different book move");
+
+        // 読み飛ばされた定跡手も思考過程に記録される。
+        let (mut engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+        engine.set_book_rng(Some(StdRng::seed_from_u64(seed)));
+        let (_, trace) = engine.do_step_traced(mv("7g7f")).unwrap();
+        assert!(trace
+            .book
+            .iter()
+            .any(|book| book.decision == BookDecision::Skipped));
+        assert_eq!(
+            trace.book.last().map(|book| book.decision),
+            Some(BookDecision::Accepted)
+        );
+
+        // 読み飛ばされた定跡手は使用済みにならず、後で指されうる。
+        let cands = engine
+            .book_state()
+            .candidates(engine.position(), engine.progress_ply());
+        for book in trace
+            .book
+            .iter()
+            .filter(|book| book.decision == BookDecision::Skipped)
+        {
+            assert!(cands.iter().any(|&(_, mv)| mv == book.mv));
+        }
+        assert!(!cands
+            .iter()
+            .any(|&(_, mv)| mv == trace.book.last().unwrap().mv));
+
+        // COM が先に指す手合割では、new_with_options() で設定すれば初手から無作為に選ばれる。
+        fn com_first_move(seed: Option<u64>) -> Option<UndoableMove> {
+            let options = EngineOptions {
+                book_rng: seed.map(StdRng::seed_from_u64),
+                ..EngineOptions::default()
+            };
+            Engine::new_with_options(Handicap::ComSenteSikenbisha, options).1
+        }
+        let default_first = com_first_move(None);
+        assert!(default_first.is_some());
+        assert!((0..10).any(|seed| com_first_move(Some(seed)) != default_first));
+    }
+
+    #[test]
+    fn test_candidate_is_suicide() {
+        // ５二の金は５八の飛車で COM 玉に釘付けにされている。
+        let engine = engine_from_sfen("sfen 4k4/4g4/9/9/9/9/9/4R4/3K5 b - 1");
+        let pos_before = engine.position().clone();
+
+        assert!(engine.candidate_is_suicide(mv("6i7i"), mv("5b4b")).unwrap());
+        assert!(!engine.candidate_is_suicide(mv("6i7i"), mv("5b5c")).unwrap());
+        assert!(!engine.candidate_is_suicide(mv("6i7i"), mv("5a4a")).unwrap());
+
+        // 局面は元に戻っている。
+        assert!(engine.position().same_position(&pos_before));
+        assert_eq!(engine.position().ply(), pos_before.ply());
+    }
+
+    #[test]
+    fn test_promoted_walk_bonus() {
+        let engine = engine_from_sfen("sfen 4k4/9/9/9/4+r4/9/9/9/4K4 b s 1");
+        let pos_before = engine.position().clone();
+
+        // HUM 玉は４九へ逃げる。５五の竜が HUM 玉に近づけば正、遠ざかれば負。
+        assert_eq!(
+            engine.promoted_walk_bonus(mv("5i4i"), mv("5e5h")).unwrap(),
+            3
+        );
+        assert_eq!(
+            engine.promoted_walk_bonus(mv("5i4i"), mv("5e5b")).unwrap(),
+            -3
+        );
+        assert_eq!(
+            engine.promoted_walk_bonus(mv("5i4i"), mv("5e4e")).unwrap(),
+            0
+        );
+
+        // 駒打ちや成駒でない駒を動かす手には適用されない。
+        assert_eq!(
+            engine.promoted_walk_bonus(mv("5i4i"), mv("S*5f")).unwrap(),
+            0
+        );
+        assert_eq!(
+            engine.promoted_walk_bonus(mv("5i4i"), mv("5a4a")).unwrap(),
+            0
+        );
+
+        // HUM の自殺手に対してはエラーとなる。
+        assert!(engine.promoted_walk_bonus(mv("5i5h"), mv("5a4a")).is_err());
+
+        // 局面は元に戻っている。
+        assert!(engine.position().same_position(&pos_before));
+        assert_eq!(engine.position().ply(), pos_before.ply());
+    }
+
+    #[test]
+    fn test_compare_candidates() {
+        let engine = engine_from_sfen("sfen 4k4/9/9/9/9/9/9/9/4K4 b - 1");
+        let mut root_eval = engine.evaluate_root();
+        root_eval.power_com = 20;
+
+        fn leaf(score_nega: u8, capture_price: u8, score_posi: u8) -> LeafEvaluation {
+            let mut eval = LeafEvaluation::new();
+            eval.score_nega = score_nega;
+            eval.capture_price = capture_price;
+            eval.score_posi = score_posi;
+            eval
+        }
+
+        // compare_candidates() の結果を確かめ、思考中の compare_with_best() とも一致することを確かめる。
+        // (HUM 玉を詰ます手は思考中は比較を経ずに採用される)
+        let check = |root_eval: &RootEvaluation,
+                     a: &LeafEvaluation,
+                     a_drop: Option<PieceKind>,
+                     b: &LeafEvaluation,
+                     b_drop: Option<PieceKind>,
+                     expected: Ordering| {
+            assert_eq!(
+                Engine::compare_candidates(root_eval, a, a_drop, b, b_drop),
+                expected
+            );
+
+            if !a.hum_is_checkmated {
+                let mut engine = engine.clone();
+                engine.naitou_best_src_value = b_drop.map_or(0, naitou_com_drop_src_value);
+                let umv = match a_drop {
+                    Some(pk) => UndoableMove::from_move_drop(Move::new_drop(pk, SQ_55)),
+                    None => UndoableMove::from_move_walk(
+                        Move::new_walk(SQ_51, SQ_41),
+                        Piece::new(COM, KING),
+                        NO_PIECE,
+                    ),
+                };
+                let (improved, _) = engine.compare_with_best(root_eval, b, a, umv);
+                assert_eq!(improved, expected == Ordering::Greater);
+            }
+        };
+
+        // 自殺手でない方が優先される。
+        let mut suicide = leaf(0, 10, 0);
+        suicide.disadv_price = 40;
+        let quiet = leaf(0, 0, 0);
+        check(&root_eval, &suicide, None, &quiet, None, Ordering::Less);
+        check(&root_eval, &quiet, None, &suicide, None, Ordering::Greater);
+
+        // score_nega が等しければ capture_price が大きい方が優先される。
+        check(
+            &root_eval,
+            &leaf(1, 3, 0),
+            None,
+            &leaf(1, 2, 0),
+            None,
+            Ordering::Greater,
+        );
+
+        // HUM 玉を詰ます手は無条件に優先される。
+        let mut mate = leaf(10, 0, 0);
+        mate.hum_is_checkmated = true;
+        check(&root_eval, &mate, None, &quiet, None, Ordering::Greater);
+
+        // 合駒でなければ、評価が同じ駒打ちより盤上の駒を動かす手が優先される。
+        check(&root_eval, &quiet, Some(PAWN), &quiet, None, Ordering::Less);
+
+        // 合駒の場合、より安い駒を打つ手が優先される。
+        let mut root_eval_check = root_eval.clone();
+        root_eval_check.disadv_price = 40;
+        check(
+            &root_eval_check,
+            &quiet,
+            Some(PAWN),
+            &quiet,
+            Some(GOLD),
+            Ordering::Greater,
+        );
+        check(
+            &root_eval_check,
+            &quiet,
+            Some(GOLD),
+            &quiet,
+            Some(PAWN),
+            Ordering::Less,
+        );
+
+        // 推移律は成り立たない: b は a に、c は b に、a は c に優先される。
+        let a = leaf(0, 0, 0);
+        let b = leaf(2, 0, 5);
+        let c = leaf(4, 3, 0);
+        check(&root_eval, &b, None, &a, None, Ordering::Greater);
+        check(&root_eval, &c, None, &b, None, Ordering::Greater);
+        check(&root_eval, &a, None, &c, None, Ordering::Greater);
+    }
+
+    #[test]
+    fn test_do_step_traced_capture_by_pawn() {
+        // COM は５三の歩で５四の金を取れる。この候補手には歩で駒を取る手の評価修正がかかる。
+        const SFEN: &str = "sfen 4k4/9/4p4/9/9/9/9/9/4K4 b G 1";
+        let mv_cand = mv("5c5d");
+
+        let mut engine = engine_from_sfen(SFEN);
+        let (_, trace) = engine.do_step_traced(mv("G*5d")).unwrap();
+        let cand = trace
+            .candidates
+            .iter()
+            .find(|cand| cand.mv == mv_cand)
+            .unwrap();
+
+        // 初期評価は、ログ出力される初期評価と同じく歩で駒を取る手の評価修正を適用したもの。
+        let mut engine = engine_from_sfen(SFEN);
+        engine.do_move_hum(mv("G*5d")).unwrap();
+        let root_eval = engine.evaluate_root();
+        let umv = engine.pos.do_move(mv_cand);
+        let raw = engine.evaluate_leaf(&root_eval, umv).unwrap();
+        let mut expected_ini = raw.clone();
+        Engine::revise_leaf_evaluation_ini(umv, &mut expected_ini);
+        assert_eq!(
+            expected_ini.score_nega,
+            raw.score_nega.wrapping_sub(1),
+            "capture-by-pawn revision must apply"
+        );
+        assert_eq!(cand.initial_eval.as_ref(), Some(&expected_ini));
+
+        let mut expected_revised = raw;
+        engine.revise_leaf_evaluation(&root_eval, umv, &mut expected_revised);
+        assert_eq!(cand.revised_eval.as_ref(), Some(&expected_revised));
+    }
+}
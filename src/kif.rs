@@ -0,0 +1,122 @@
+//! KIF 形式の棋譜出力。
+
+use std::fmt::Write as _;
+
+use crate::shogi::*;
+
+/// 指し手の配列を KIF 形式の指し手部分(ヘッダ行および各指し手の行)にエンコードする。
+/// 消費時間は出力しない。合法性チェックは一切行わない。
+///
+/// 各行は改行で終わる。
+pub fn kif_encode_moves<T>(umvs: T) -> String
+where
+    T: AsRef<[UndoableMove]>,
+{
+    let mut s = String::from("手数----指手---------消費時間--\n");
+
+    let mut dst_prev: Option<Square> = None;
+    for (i, &umv) in umvs.as_ref().iter().enumerate() {
+        writeln!(s, "{:>4} {}", i + 1, kif_encode_move(umv, dst_prev)).unwrap();
+        dst_prev = Some(umv.dst());
+    }
+
+    s
+}
+
+/// 指し手を KIF 形式の指し手文字列 ("７六歩(77)" など) にエンコードする。
+/// 合法性チェックは一切行わない。
+///
+/// `dst_prev` は直前の指し手の移動先で、これと移動先が一致する場合は "同　" と表記する。
+pub fn kif_encode_move(umv: UndoableMove, dst_prev: Option<Square>) -> String {
+    let mut s = String::new();
+
+    let dst = umv.dst();
+    if dst_prev == Some(dst) {
+        s.push_str("同　");
+    } else {
+        write!(s, "{}", dst).unwrap();
+    }
+
+    if umv.is_drop() {
+        write!(s, "{}打", kif_piece_kind_name(umv.dropped_piece_kind())).unwrap();
+        return s;
+    }
+
+    let src = umv.src();
+    let pc_src = umv.piece_src();
+    s.push_str(kif_piece_kind_name(pc_src.kind()));
+
+    if umv.is_promotion() {
+        s.push('成');
+    } else if pc_src.is_promotable()
+        && (src.is_promotion_zone(pc_src.side()) || dst.is_promotion_zone(pc_src.side()))
+    {
+        s.push_str("不成");
+    }
+
+    write!(s, "({}{})", src.col().inner() + 1, src.row().inner() + 1).unwrap();
+
+    s
+}
+
+/// KIF 形式における駒種名を返す。
+fn kif_piece_kind_name(pk: PieceKind) -> &'static str {
+    match pk {
+        PAWN => "歩",
+        LANCE => "香",
+        KNIGHT => "桂",
+        SILVER => "銀",
+        BISHOP => "角",
+        ROOK => "飛",
+        GOLD => "金",
+        KING => "玉",
+        PRO_PAWN => "と",
+        PRO_LANCE => "成香",
+        PRO_KNIGHT => "成桂",
+        PRO_SILVER => "成銀",
+        HORSE => "馬",
+        DRAGON => "龍",
+        _ => panic!("invalid piece kind: {:?}", pk),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    use super::*;
+
+    #[test]
+    fn test_kif_encode_moves() {
+        let umvs = [
+            UndoableMove::from_move_walk(Move::new_walk(SQ_77, SQ_76), H_PAWN, NO_PIECE),
+            UndoableMove::from_move_walk(Move::new_walk(SQ_33, SQ_34), C_PAWN, NO_PIECE),
+            UndoableMove::from_move_walk(
+                Move::new_walk_promotion(SQ_88, SQ_22),
+                H_BISHOP,
+                C_BISHOP,
+            ),
+            UndoableMove::from_move_walk(Move::new_walk(SQ_31, SQ_22), C_SILVER, H_HORSE),
+            UndoableMove::from_move_drop(Move::new_drop(BISHOP, SQ_45)),
+            UndoableMove::from_move_walk(Move::new_walk(SQ_22, SQ_33), C_SILVER, NO_PIECE),
+            UndoableMove::from_move_walk(Move::new_walk(SQ_45, SQ_23), H_BISHOP, NO_PIECE),
+        ];
+
+        assert_eq!(
+            kif_encode_moves(umvs),
+            "\
+手数----指手---------消費時間--
+   1 ７六歩(77)
+   2 ３四歩(33)
+   3 ２二角成(88)
+   4 同　銀(31)
+   5 ４五角打
+   6 ３三銀(22)
+   7 ２三角不成(45)
+"
+        );
+
+        assert_eq!(kif_encode_moves([]), "手数----指手---------消費時間--\n");
+    }
+}
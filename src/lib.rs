@@ -4,6 +4,7 @@ mod bitop;
 mod book;
 pub mod effect;
 mod engine;
+mod kif;
 mod movegen;
 pub mod myarray;
 pub mod mylog;
@@ -21,6 +22,7 @@ pub mod emu;
 pub use self::bitboard::*;
 pub use self::book::*;
 pub use self::engine::*;
+pub use self::kif::*;
 pub use self::movegen::*;
 pub use self::naitou::*;
 pub use self::perft::*;
@@ -8,6 +8,8 @@
 // 駒打ちの指し手は歩、香、桂、銀、金、角、飛車の順に生成される。
 // 盤上の駒を動かす指し手は駒種ごとに移動先の順序が決まっている。また、成れる場合は必ず成る。
 
+use arrayvec::ArrayVec;
+
 use crate::bbs;
 use crate::bitboard::Bitboard;
 use crate::naitou::*;
@@ -18,41 +20,76 @@ use super::MoveArray;
 
 /// 指定した局面における全ての COM 側の指し手を生成する。順序は原作通りになっている。
 ///
+/// 思考ルーチンによる候補手の比較結果は生成順序に依存するので(評価が同等の指し手のどれを採用するかなど)、
+/// この順序は COM の指し手の再現性に直結する。生成順序を変えてはならない。
+///
 /// 生成された指し手は違法手も含む。自殺手判定などは思考ルーチン側で行う(必ずしも正しくない)。
 ///
 /// 局面は COM の手番であることを仮定している。
 pub fn generate_moves_com(pos: &Position) -> MoveArray {
-    debug_assert_eq!(pos.side_to_move(), COM);
-
-    // 盤上の駒の移動先の bitboard を予め求めておく。つまりそれは自駒のないマスである。
-    let bb_walk_target = !pos.bb_occupied_side(COM);
-
-    // 歩を打てるマスの bitboard を予め求めておく。
-    let bb_pawn_drop = bbs::pawn_drop_mask(COM, pos.bb_piece(COM, PAWN));
+    let gen = ComMoveGenerator::new(pos);
 
     let mut mvs = MoveArray::new();
 
-    // 原作通りの順序でマスを列挙し、空白なら駒打ちを、COM 駒ならそれを動かす手を生成。
+    // 原作通りの順序でマスを列挙し、各マスについて指し手を生成。
     for sq in naitou_squares() {
+        gen.generate_square(pos, sq, &mut mvs);
+    }
+
+    mvs
+}
+
+/// 1 マス分の COM 側の指し手を収める配列。
+///
+/// 1 マスから生成される指し手は竜・馬の 20 手が最大。
+pub(crate) type SquareMoveArray = ArrayVec<Move, 32>;
+
+/// COM 側の指し手をマス単位で生成する。
+///
+/// `naitou_squares()` の順にマスを渡せば `generate_moves_com()` と同じ順序になる。
+/// 途中で局面を変更した場合、次のマスを渡す前に元の局面に戻しておかなければならない。
+pub(crate) struct ComMoveGenerator {
+    bb_walk_target: Bitboard,
+    bb_pawn_drop: Bitboard,
+}
+
+impl ComMoveGenerator {
+    /// 局面は COM の手番であることを仮定している。
+    pub(crate) fn new(pos: &Position) -> Self {
+        debug_assert_eq!(pos.side_to_move(), COM);
+
+        Self {
+            // 盤上の駒の移動先の bitboard を予め求めておく。つまりそれは自駒のないマスである。
+            bb_walk_target: !pos.bb_occupied_side(COM),
+            // 歩を打てるマスの bitboard を予め求めておく。
+            bb_pawn_drop: bbs::pawn_drop_mask(COM, pos.bb_piece(COM, PAWN)),
+        }
+    }
+
+    /// マス `sq` が空白なら駒打ちの指し手を、COM 駒ならそれを動かす指し手を `mvs` に追加する。
+    pub(crate) fn generate_square<const N: usize>(
+        &self,
+        pos: &Position,
+        sq: Square,
+        mvs: &mut ArrayVec<Move, N>,
+    ) {
         match pos.board()[sq] {
-            NO_PIECE => generate_moves_com_drop(pos, sq, bb_pawn_drop, &mut mvs),
+            NO_PIECE => generate_moves_com_drop(pos, sq, self.bb_pawn_drop, mvs),
             pc if pc.side() == COM => {
-                generate_moves_com_walk(pos, sq, pc.kind(), bb_walk_target, &mut mvs)
+                generate_moves_com_walk(pos, sq, pc.kind(), self.bb_walk_target, mvs)
             }
             _ => {}
         }
     }
-
-    mvs
 }
 
 /// マス `src` にある COM 駒種 `pk` を動かす指し手を生成する。
-fn generate_moves_com_walk(
+fn generate_moves_com_walk<const N: usize>(
     pos: &Position,
     src: Square,
     pk: PieceKind,
     bb_target: Bitboard,
-    mvs: &mut MoveArray,
+    mvs: &mut ArrayVec<Move, N>,
 ) {
     let src_ww = SquareWithWall::from(src);
 
@@ -87,7 +124,12 @@ fn generate_moves_com_walk(
 }
 
 /// 駒種 `pk` を `src` から `dst` へ動かす指し手を生成する。成れる場合は必ず成る。
-fn generate_walk_helper(pk: PieceKind, src: Square, dst: Square, mvs: &mut MoveArray) {
+fn generate_walk_helper<const N: usize>(
+    pk: PieceKind,
+    src: Square,
+    dst: Square,
+    mvs: &mut ArrayVec<Move, N>,
+) {
     let promo = pk.is_promotable() && (src.is_promotion_zone(COM) || dst.is_promotion_zone(COM));
 
     if promo {
@@ -206,11 +248,11 @@ const fn effect_ranged(pk: PieceKind) -> &'static [i32] {
 }
 
 /// マス `dst` を対象とする駒打ちの指し手を生成する。
-fn generate_moves_com_drop(
+fn generate_moves_com_drop<const N: usize>(
     pos: &Position,
     dst: Square,
     bb_pawn_drop: Bitboard,
-    mvs: &mut MoveArray,
+    mvs: &mut ArrayVec<Move, N>,
 ) {
     let us = pos.side_to_move();
     let hand = pos.hand(us);
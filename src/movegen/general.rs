@@ -48,6 +48,42 @@ pub fn generate_moves(pos: &Position) -> MoveArray {
     mvs
 }
 
+/// `generate_moves_standard()` のオプション。
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct GenOptions {
+    /// 歩、角、飛車が成れるとき、不成の指し手を生成しない。
+    ///
+    /// これらの駒は成っても利きが減らないので、通常は不成を考慮する必要がない。
+    /// (ただし不成により打ち歩詰めを回避できる局面などもまれに存在する)
+    pub prune_underpromotion: bool,
+}
+
+/// 指定した局面における全ての合法手を、通常の将棋のルールに基づいて生成する。
+///
+/// `Position::legal_moves()` と同様に自殺手と打ち歩詰めを除いた上で、`opts` に従って指し手を絞り込む。
+/// 連続王手の千日手は除かれない。生成順は未規定。
+///
+/// 関数から戻ったとき、`pos` は呼び出し前の局面に戻っている。
+pub fn generate_moves_standard(pos: &mut Position, opts: GenOptions) -> MoveArray {
+    let us = pos.side_to_move();
+
+    let mut mvs = pos.legal_moves();
+
+    if opts.prune_underpromotion {
+        mvs.retain(|mv| {
+            if mv.is_drop() || mv.is_promotion() {
+                return true;
+            }
+            let (src, dst) = (mv.src(), mv.dst());
+            let can_promote = src.is_promotion_zone(us) || dst.is_promotion_zone(us);
+            let always_promote = matches!(pos.board()[src].kind(), PAWN | BISHOP | ROOK);
+            !(can_promote && always_promote)
+        });
+    }
+
+    mvs
+}
+
 /// 盤上の歩を動かす指し手を生成する。
 #[inline]
 fn generate_moves_walk_pawn(pos: &Position, bb_target: Bitboard, mvs: &mut MoveArray) {
@@ -426,6 +462,44 @@ fn generate_evasions_king(pos: &Position, src: Square, bb_dst: Bitboard, mvs: &m
     });
 }
 
+/// 指定した局面で `side` 側の玉が 1 手で移動できるマスを返す。手番は問わない。
+///
+/// 自駒がなく、玉を動かした後に敵の利きがないマスのみを返す。
+/// `generate_evasions()` と異なり、移動と逆方向からの利き(玉の背後へ抜ける飛び駒の利き)も考慮する。
+pub fn king_escape_squares(pos: &Position, side: Side) -> Bitboard {
+    let them = side.inv();
+    let sq_king = pos.king_square(side);
+
+    // 玉を取り除いた盤面で飛び駒の利きを求めれば、玉の背後への利きも考慮される。
+    let occ = pos.bb_occupied() ^ Bitboard::from(sq_king);
+    let bb_rook = pos.bb_piece(them, ROOK) | pos.bb_piece(them, DRAGON);
+    let bb_bishop = pos.bb_piece(them, BISHOP) | pos.bb_piece(them, HORSE);
+    let bb_lance = pos.bb_piece(them, LANCE);
+
+    let mut bb_escape = Bitboard::zero();
+
+    let bb_dst = !pos.bb_occupied_side(side) & bbs::king_effect(sq_king);
+    bb_dst.for_each_square(|dst| {
+        // 現局面で敵の利きがあるマスには行けない。
+        if pos.effect_count_board(them)[dst] > 0 {
+            return;
+        }
+
+        // 玉を動かした後に飛び駒の利きがあるマスにも行けない。
+        // (`side` 側の駒として dst から逆に利きを伸ばし、敵の飛び駒に当たるかを見る)
+        let attacked = bbs::rook_effect(dst, occ).test(bb_rook)
+            || bbs::bishop_effect(dst, occ).test(bb_bishop)
+            || bbs::lance_effect(side, dst, occ).test(bb_lance);
+        if attacked {
+            return;
+        }
+
+        bb_escape |= Bitboard::from(dst);
+    });
+
+    bb_escape
+}
+
 /// 指定した局面で手番の側がチェックメイト(**打ち歩含む**)されているかどうかを返す。
 /// 手番の側に王手がかかっていることを仮定している。
 ///
@@ -455,6 +529,24 @@ pub fn position_is_checkmated_naitou(pos: &mut Position) -> bool {
     position_is_checkmated_impl(pos, bb_drop_target)
 }
 
+/// 指定した局面で手番の側が通常の将棋のルールでチェックメイトされているかどうかを返す。
+/// 手番の側に王手がかかっていることを仮定している。
+///
+/// **打ち歩詰めは詰みとみなさない**。通常の将棋では打ち歩詰めは攻め方の反則であり、勝ちにはならないため。
+/// これに対し `position_is_checkmated()`, `position_is_checkmated_naitou()` は打ち歩詰めも詰みとみなす。
+/// (原作では HUM 側の打ち歩詰めが許されるので、後者が原作通りの判定となる)
+///
+/// 打ち歩詰めかどうかは局面だけからは判定できない(歩を突いての詰みは合法)ので、
+/// 直前の指し手 `last_move` を与える。`None` の場合、通常の詰みとして判定する。
+///
+/// 関数から戻ったとき、`pos` は呼び出し前の局面に戻っている。
+pub fn is_checkmated_standard(pos: &mut Position, last_move: Option<UndoableMove>) -> bool {
+    let is_pawn_drop =
+        last_move.is_some_and(|umv| umv.is_drop() && umv.dropped_piece_kind() == PAWN);
+
+    !is_pawn_drop && position_is_checkmated(pos)
+}
+
 /// チェックメイト判定ルーチン本体。駒打ち対象マスとして `bb_drop_target` を与える。
 /// 手番の側に王手がかかっていることを仮定している。
 #[inline]
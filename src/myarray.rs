@@ -12,6 +12,22 @@ pub struct MyArray1<V, K, const N: usize> {
     _phantom: PhantomData<fn() -> K>,
 }
 
+/// `MyArray1` のインデックス型。配列の添字からインデックスを復元できる。
+pub trait ArrayIndex: Into<usize> {
+    /// 配列の添字に対応するインデックスを返す。`Into<usize>` の逆変換。
+    fn from_array_index(i: usize) -> Self;
+}
+
+impl<V, K: ArrayIndex, const N: usize> MyArray1<V, K, N> {
+    /// 各インデックスについて `f` を呼んだ結果を要素とする配列を返す。`std::array::from_fn()` と同様。
+    pub fn from_fn<F>(mut f: F) -> Self
+    where
+        F: FnMut(K) -> V,
+    {
+        Self::from(std::array::from_fn(|i| f(K::from_array_index(i))))
+    }
+}
+
 impl<V, K, const N: usize> From<[V; N]> for MyArray1<V, K, N> {
     fn from(inner: [V; N]) -> Self {
         Self {
@@ -94,3 +110,31 @@ pub type MyArray5<
     const N4: usize,
     const N5: usize,
 > = MyArray1<MyArray4<V, K2, K3, K4, K5, N2, N3, N4, N5>, K1, N1>;
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    use crate::shogi::*;
+
+    use super::*;
+
+    #[test]
+    fn test_from_fn() {
+        let ary = MyArray1::<u32, Square, 81>::from_fn(|sq| {
+            10 * (u32::from(sq.col()) + 1) + (u32::from(sq.row()) + 1)
+        });
+
+        let mut expected = MyArray1::<u32, Square, 81>::default();
+        for sq in Square::iter() {
+            expected[sq] = 10 * (u32::from(sq.col()) + 1) + (u32::from(sq.row()) + 1);
+        }
+        assert_eq!(ary, expected);
+        assert_eq!(ary[SQ_76], 76);
+
+        let ary = MyArray1::<Side, Side, 2>::from_fn(|side| side.inv());
+        assert_eq!(ary[HUM], COM);
+        assert_eq!(ary[COM], HUM);
+    }
+}
@@ -1,12 +1,60 @@
 //! 思考ログ出力。
+//!
+//! `engine-trace` feature (既定で有効) が無効な場合、全てのログ出力関数は何もしない。
+//! 関数本体は空になるので、呼び出しごと最適化で消える。
+//!
+//! feature が有効でも、`quiet()` が返すガードの生存中はそのスレッドのログ出力は抑制される。
 
-use log::info;
+/// `engine-trace` feature が有効な場合のみ `log::info!` を呼ぶ。
+///
+/// feature が無効でも引数は型検査されるので、未使用変数の警告などは出ない。
+macro_rules! info {
+    ($($arg:tt)+) => {
+        if cfg!(feature = "engine-trace") && !is_quiet() {
+            log::info!($($arg)+);
+        }
+    };
+}
+
+use std::cell::Cell;
 
 use crate::book::Formation;
 use crate::effect::EffectCountBoard;
 use crate::engine::{LeafEvaluation, RootEvaluation};
 use crate::shogi::*;
 
+thread_local! {
+    static QUIET: Cell<bool> = const { Cell::new(false) };
+}
+
+/// 生存している間、このスレッドのログ出力を抑制するガード。`quiet()` で作る。
+#[must_use]
+pub struct QuietGuard {
+    prev: bool,
+}
+
+impl Drop for QuietGuard {
+    fn drop(&mut self) {
+        if cfg!(feature = "engine-trace") {
+            QUIET.with(|quiet| quiet.set(self.prev));
+        }
+    }
+}
+
+/// 返されたガードが生存している間、このスレッドのログ出力を抑制する。入れ子にしてもよい。
+///
+/// 思考エンジンに対する読み取り専用の問い合わせが思考ログに紛れ込まないようにするために使う。
+pub fn quiet() -> QuietGuard {
+    let prev = cfg!(feature = "engine-trace") && QUIET.with(|quiet| quiet.replace(true));
+
+    QuietGuard { prev }
+}
+
+/// ログ出力が抑制されているかどうかを返す。
+fn is_quiet() -> bool {
+    QUIET.with(Cell::get)
+}
+
 /// 思考開始ログを出力する。
 pub fn log_think_start(ply: u32) {
     info!(
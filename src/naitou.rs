@@ -28,6 +28,20 @@ arg_enum! {
 }
 
 impl Handicap {
+    /// 全ての手合割を返す。
+    pub const fn all() -> [Self; 8] {
+        [
+            Self::HumSenteSikenbisha,
+            Self::HumSenteNakabisha,
+            Self::HumHishaochi,
+            Self::HumNimaiochi,
+            Self::ComSenteSikenbisha,
+            Self::ComSenteNakabisha,
+            Self::ComHishaochi,
+            Self::ComNimaiochi,
+        ]
+    }
+
     /// 開始局面および時間制限設定に対応する手合割を返す。
     /// 対応する手合割が見つからなければエラーを返す。
     pub fn from_startpos(
@@ -1,5 +1,10 @@
+use std::io::BufRead;
+
+use anyhow::{anyhow, ensure, Context as _};
+
 use crate::movegen::*;
 use crate::position::Position;
+use crate::sfen::sfen_decode;
 use crate::shogi::*;
 
 /// perft の末端ノード。`perft()` のコールバックに渡される。
@@ -58,6 +63,32 @@ where
     perft_dfs(pos, None, depth, &mut f);
 }
 
+/// 指定した深さの perft を行い、ルート局面の各合法手について、その部分木の末端ノード数をコールバックで通知する。
+///
+/// いわゆる divide の結果を、ルートの指し手 1 つの探索が終わるたびに逐次返すもの。
+/// 長時間の perft で進捗を表示するのに使える。通知された末端ノード数の総和は `perft()` の末端ノード数と一致する。
+///
+/// `depth` は 1 以上でなければならない。`pos` は合法局面でなければならない。
+/// 関数から戻ったとき、`pos` は呼び出し前の局面に戻っている。
+pub fn perft_with_progress<F>(pos: &mut Position, depth: u32, mut on_root_done: F)
+where
+    F: FnMut(Move, u64),
+{
+    assert!(depth >= 1, "depth must be >= 1");
+
+    for mv in pos.legal_moves() {
+        let mut count = 0;
+
+        let umv = pos.do_move(mv);
+        perft_dfs(pos, Some(umv), depth - 1, &mut |_: &PerftLeafNode| {
+            count += 1
+        });
+        pos.undo_move(umv);
+
+        on_root_done(mv, count);
+    }
+}
+
 /// perft 再帰関数。
 ///
 /// 呼び出された時点で `pos` は合法とは限らない。具体的には以下の可能性がある:
@@ -117,3 +148,117 @@ where
     let leaf = PerftLeafNode::new(pos, umv, checked, checkmated);
     f(&leaf);
 }
+
+/// perft テストスイートの 1 局面分の結果。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SuiteResult {
+    /// 入力における行番号 (1 から始まる)。
+    pub line: usize,
+
+    /// 局面文字列。
+    pub sfen: String,
+
+    /// 各深さの結果。入力に記述された順に並ぶ。
+    pub depths: Vec<SuiteDepthResult>,
+}
+
+impl SuiteResult {
+    /// 全ての深さで期待値と一致したかどうかを返す。
+    pub fn passed(&self) -> bool {
+        self.depths.iter().all(SuiteDepthResult::passed)
+    }
+}
+
+/// perft テストスイートの 1 局面、1 深さ分の結果。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SuiteDepthResult {
+    pub depth: u32,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl SuiteDepthResult {
+    /// 期待値と一致したかどうかを返す。
+    pub fn passed(&self) -> bool {
+        self.expected == self.actual
+    }
+}
+
+/// perft テストスイートを実行し、各局面の結果を返す。
+///
+/// 入力の各行は EPD 風の `<sfen> ; D1 <n1> ; D2 <n2> ; ...` という形式。
+/// `<sfen>` は `sfen_decode()` が受け付ける文字列で、指し手を含んでもよい(指し手を適用した局面が対象となる)。
+/// 空行および `#` で始まる行は無視する。
+///
+/// 期待値との不一致はエラーとせず結果に含める。入力の読み込みや構文解析に失敗した場合はエラーを返す。
+pub fn run_suite(reader: impl BufRead) -> anyhow::Result<Vec<SuiteResult>> {
+    let mut results = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let result = run_suite_line(line_no, line).with_context(|| format!("line {}", line_no))?;
+        results.push(result);
+    }
+
+    Ok(results)
+}
+
+fn run_suite_line(line_no: usize, line: &str) -> anyhow::Result<SuiteResult> {
+    let mut fields = line.split(';').map(str::trim);
+
+    let sfen = fields.next().expect("split() yields at least one field");
+    let (side_to_move, board, hands, mvs) = sfen_decode(sfen)?;
+    let mut pos = Position::new(side_to_move, board, hands);
+    pos.apply_moves(&mvs).context("cannot apply moves")?;
+
+    let mut depths = Vec::new();
+    for field in fields {
+        let (depth, expected) = parse_suite_depth(field)?;
+
+        let mut actual = 0;
+        perft(&mut pos, depth, |_| actual += 1);
+
+        depths.push(SuiteDepthResult {
+            depth,
+            expected,
+            actual,
+        });
+    }
+    ensure!(!depths.is_empty(), "no depth specified");
+
+    Ok(SuiteResult {
+        line: line_no,
+        sfen: sfen.to_owned(),
+        depths,
+    })
+}
+
+/// `D<depth> <count>` をパースする。
+fn parse_suite_depth(s: &str) -> anyhow::Result<(u32, u64)> {
+    let mut tokens = s.split_ascii_whitespace();
+
+    let depth = tokens
+        .next()
+        .and_then(|token| token.strip_prefix('D'))
+        .ok_or_else(|| anyhow!("invalid depth field: {}", s))?;
+    let depth: u32 = depth
+        .parse()
+        .with_context(|| format!("invalid depth: {}", s))?;
+
+    let count = tokens
+        .next()
+        .ok_or_else(|| anyhow!("count not found: {}", s))?;
+    let count: u64 = count
+        .parse()
+        .with_context(|| format!("invalid count: {}", s))?;
+
+    ensure!(tokens.next().is_none(), "redundant token: {}", s);
+
+    Ok((depth, count))
+}
@@ -5,12 +5,21 @@
 // * [Long Effect Library 完全解説 その3](https://yaneuraou.yaneu.com/2016/01/25/long-effect-library-%e5%ae%8c%e5%85%a8%e8%a7%a3%e8%aa%ac-%e3%81%9d%e3%81%ae3/)
 // * [Long Effect Library 完全解説 その4](https://yaneuraou.yaneu.com/2016/01/29/long-effect-library-%e5%ae%8c%e5%85%a8%e8%a7%a3%e8%aa%ac-%e3%81%9d%e3%81%ae4/)
 
+use std::ops::ControlFlow;
+
+use anyhow::ensure;
+
 use crate::bbs;
 use crate::bitboard::Bitboard;
 use crate::effect::*;
-use crate::movegen::position_is_checkmated;
+use crate::movegen::{
+    generate_evasions, generate_moves, position_is_checkmated, ComMoveGenerator, MoveArray,
+    SquareMoveArray,
+};
 use crate::myarray::*;
 use crate::mynum::WrappingAddAssign as _;
+use crate::naitou::naitou_squares;
+use crate::sfen::sfen_encode_position;
 use crate::shogi::*;
 
 type BbOccSide = MyArray1<Bitboard, Side, 2>;
@@ -18,6 +27,60 @@ type BbPk = MyArray1<Bitboard, PieceKind, 15>;
 type KingSq = MyArray1<Square, Side, 2>;
 type EffectCountBoards = MyArray1<EffectCountBoard, Side, 2>;
 
+/// 盤面と手駒の変化を受け取るオブザーバ。
+///
+/// `Position::do_move_with_delta()`, `Position::undo_move_with_delta()` に渡すと、
+/// 盤面と手駒の変化がイベントとして通知される。外部で独自のハッシュ値などを差分更新するのに使う。
+///
+/// イベントは以下の順に通知される:
+///
+/// * 盤上の駒を動かす手: 移動元の駒の除去、(駒取りなら)捕獲される駒の除去、手駒の増加、移動先への駒の配置
+/// * 駒打ち: 手駒の減少、移動先への駒の配置
+///
+/// undo 時はそれぞれ逆の変化が逆順に通知される。
+pub trait BoardDelta {
+    /// マス `sq` に駒 `pc` が置かれた。
+    fn put(&mut self, sq: Square, pc: Piece);
+
+    /// マス `sq` から駒 `pc` が除去された。
+    fn remove(&mut self, sq: Square, pc: Piece);
+
+    /// 陣営 `side` の手駒の駒種 `pk` の個数が `delta` だけ変化した。
+    fn change_hand(&mut self, side: Side, pk: PieceKind, delta: i32);
+}
+
+/// 盤上の駒を動かす指し手について、成りが可能かどうか。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PromotionStatus {
+    /// 必ず成らなければならない(不成だと行きどころのない駒が生じる)。
+    Mandatory,
+
+    /// 成りと不成のどちらも可能。
+    Optional,
+
+    /// 成れない。
+    None,
+}
+
+/// 手番、盤面、両陣営の手駒を詰めたバイト列。手数は含まない。`Position::signature()` で得られる。
+///
+/// 比較可能かつハッシュ可能なので、局面をキーとする `BTreeMap`, `HashMap` などに使える。
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct PositionSignature([u8; 96]);
+
+impl PositionSignature {
+    /// 内部のバイト列を返す。(手番 1 バイト、盤面 81 バイト、手駒 7*2 バイトの順)
+    pub fn as_bytes(&self) -> &[u8; 96] {
+        &self.0
+    }
+}
+
+#[cfg(debug_assertions)]
+thread_local! {
+    /// 利きの自己検査を行うかどうか。`Position::set_effect_self_check()` で設定する。
+    static EFFECT_SELF_CHECK: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
 /// 局面。
 #[derive(Clone, Debug)]
 pub struct Position {
@@ -35,7 +98,8 @@ pub struct Position {
     board: Board,
     hands: Hands,
     side_to_move: Side,
-    ply: u32, // 常に 1 から始まるものとする。
+    ply: u32,           // 常に 1 から始まるものとする。
+    initial_side: Side, // 手数 1 のときの手番 (手合割によっては COM が先に指す)
 
     king_sq: KingSq, // 各陣営の玉位置
 
@@ -91,6 +155,7 @@ impl Position {
             hands,
             side_to_move,
             ply: 1,
+            initial_side: side_to_move,
 
             king_sq,
 
@@ -104,6 +169,62 @@ impl Position {
         this
     }
 
+    /// 陣営を入れ替えた局面を返す。
+    ///
+    /// 盤面は 180 度回転して駒の陣営を入れ替え、両陣営の手駒と手番も入れ替える。
+    /// 利きなどは改めて計算される。手数は 1 に戻る。
+    pub fn flipped(&self) -> Self {
+        let board = self.board.flip_sides();
+        let hands = Hands::from([self.hands[COM], self.hands[HUM]]);
+
+        Self::new(self.side_to_move.inv(), board, hands)
+    }
+
+    /// `other` と手番、盤面、両陣営の手駒が等しいかどうかを返す。手数は無視する。
+    pub fn same_position(&self, other: &Self) -> bool {
+        self.side_to_move == other.side_to_move
+            && self.board == other.board
+            && self.hands == other.hands
+    }
+
+    /// 手番、盤面、両陣営の手駒から計算したハッシュ値(FNV-1a)を返す。手数や利きなどは無視する。
+    ///
+    /// 差分計算はせず、呼ぶたびに盤面全体を走査する。テーブルも不要で、`do_move()` にも影響しない。
+    /// `same_position()` が真となる 2 局面は同じ値を返す。
+    pub fn content_hash(&self) -> u64 {
+        const FNV_OFFSET_BASIS: u64 = 0xCBF29CE484222325;
+        const FNV_PRIME: u64 = 0x100000001B3;
+
+        self.signature()
+            .as_bytes()
+            .iter()
+            .fold(FNV_OFFSET_BASIS, |hash, &byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+            })
+    }
+
+    /// 手番、盤面、両陣営の手駒からなる `PositionSignature` を返す。手数や利きなどは無視する。
+    ///
+    /// `same_position()` が真となる 2 局面は、かつそのときに限り等しい値を返す。
+    pub fn signature(&self) -> PositionSignature {
+        let mut bytes = [0; 96];
+
+        bytes[0] = u32::from(self.side_to_move) as u8;
+
+        for (i, sq) in Square::iter().enumerate() {
+            bytes[1 + i] = u32::from(self.board[sq]) as u8;
+        }
+
+        for (i, (side, pk)) in Side::iter()
+            .flat_map(|side| PieceKind::iter_hand().map(move |pk| (side, pk)))
+            .enumerate()
+        {
+            bytes[82 + i] = self.hands[side][pk] as u8;
+        }
+
+        PositionSignature(bytes)
+    }
+
     /// 手数を返す。
     pub fn ply(&self) -> u32 {
         self.ply
@@ -114,6 +235,77 @@ impl Position {
         self.side_to_move
     }
 
+    /// HUM の手番かどうかを返す。
+    pub fn is_hum_turn(&self) -> bool {
+        self.side_to_move == HUM
+    }
+
+    /// 手数 1 のときの手番、つまり局面を作った時点の手番を返す。
+    ///
+    /// 手数は手番によらず常に 1 から始まるので、手数の偶奇だけでは手番は決まらない。
+    /// 手数が奇数ならこの陣営の手番、偶数なら相手の手番である。
+    pub fn initial_side(&self) -> Side {
+        self.initial_side
+    }
+
+    /// 手数と手番の関係が崩れていないことを確認する(デバッグビルドでのみ有効)。
+    pub fn debug_check_turn_parity(&self) {
+        debug_assert!(self.ply >= 1, "ply must be >= 1: {}", self.ply);
+        debug_assert_eq!(
+            self.side_to_move == self.initial_side,
+            self.ply % 2 == 1,
+            "turn parity broken: ply={}, side_to_move={}, initial_side={}",
+            self.ply,
+            self.side_to_move,
+            self.initial_side
+        );
+    }
+
+    /// 利きの自己検査を有効/無効にする(デバッグビルドでのみ有効)。
+    ///
+    /// 有効にすると、`do_move()`, `undo_move()` のたびに利きを愚直に再計算し、
+    /// 差分更新の結果と食い違っていれば両者を出力して panic する。
+    /// 非常に遅いので、デフォルトでは無効。設定はスレッドごとに独立している。
+    #[cfg(debug_assertions)]
+    pub fn set_effect_self_check(enabled: bool) {
+        EFFECT_SELF_CHECK.with(|flag| flag.set(enabled));
+    }
+
+    /// 利きの自己検査が有効ならば、利きを再計算して差分更新の結果と比較する。
+    #[cfg(debug_assertions)]
+    fn debug_check_effects(&self, name: &str, umv: UndoableMove) {
+        if !EFFECT_SELF_CHECK.with(|flag| flag.get()) {
+            return;
+        }
+
+        let (effect_counts, ranged_effects) = calc_effect(self);
+        let ng_count_hum = self.effect_counts[HUM] != effect_counts[HUM];
+        let ng_count_com = self.effect_counts[COM] != effect_counts[COM];
+        let ng_range = self.ranged_effects != ranged_effects;
+        let ng = ng_count_hum || ng_count_com || ng_range;
+        if ng {
+            eprintln!("[{}() failed]", name);
+            eprintln!("{}", self);
+            eprintln!("指し手: {}", umv);
+        }
+        if ng_count_hum {
+            eprintln!("--- HUM counts ---");
+            eprintln!("{}", self.effect_counts[HUM]);
+            eprintln!("{}", effect_counts[HUM]);
+        }
+        if ng_count_com {
+            eprintln!("--- COM counts ---");
+            eprintln!("{}", self.effect_counts[COM]);
+            eprintln!("{}", effect_counts[COM]);
+        }
+        if ng_range {
+            eprintln!("--- ranged ---");
+            eprintln!("{}", self.ranged_effects);
+            eprintln!("{}", ranged_effects);
+        }
+        assert!(!ng, "{}() broke effects: {}", name, umv);
+    }
+
     /// 盤面への参照を返す。
     pub fn board(&self) -> &Board {
         &self.board
@@ -124,11 +316,49 @@ impl Position {
         &self.hands
     }
 
+    /// 局面を sfen 局面文字列にエンコードする。`sfen_encode_position()` を参照。
+    ///
+    /// 手駒は USI の規定通り、先手(HUM)、後手(COM)の順に、それぞれ飛, 角, 金, 銀, 桂, 香, 歩の順で並ぶ。
+    pub fn to_sfen(&self) -> String {
+        sfen_encode_position(self.side_to_move, &self.board, &self.hands)
+    }
+
+    /// 現局面で指す指し手を、手番記号と駒種付きの文字列 ("▲７六歩", "△５五角打" など) にする。
+    ///
+    /// 手番記号は手番から、駒種は盤面から決まる。移動元は出力しない。
+    /// `mv` は現局面における疑似合法手であると仮定している。
+    pub fn move_to_string(&self, mv: Move) -> String {
+        let mark = if self.side_to_move == HUM {
+            '▲'
+        } else {
+            '△'
+        };
+
+        if mv.is_drop() {
+            format!("{}{}{}打", mark, mv.dst(), mv.dropped_piece_kind())
+        } else {
+            let pk = self.board[mv.src()].kind();
+            let promo = if mv.is_promotion() { "成" } else { "" };
+            format!("{}{}{}{}", mark, mv.dst(), pk, promo)
+        }
+    }
+
     /// 指定した陣営の手駒への参照を返す。
     pub fn hand(&self, side: Side) -> &Hand {
         &self.hands[side]
     }
 
+    /// 両陣営の手駒のうち個数が 0 でないものを (陣営, 駒種, 個数) として列挙する。
+    /// HUM 側が先で、各陣営内では駒種の昇順となる。
+    pub fn hands_iter(&self) -> impl Iterator<Item = (Side, PieceKind, u32)> + '_ {
+        Side::iter().flat_map(move |side| self.hands[side].iter().map(move |(pk, n)| (side, pk, n)))
+    }
+
+    /// 指定した陣営が持っている(個数が 0 でない)手駒の駒種を昇順に列挙する。
+    pub fn hand_kinds(&self, side: Side) -> impl Iterator<Item = PieceKind> + '_ {
+        self.hands[side].iter().map(|(pk, _)| pk)
+    }
+
     /// 陣営を区別しない occupied bitboard を返す。
     pub fn bb_occupied(&self) -> Bitboard {
         self.bb_occ
@@ -158,22 +388,208 @@ impl Position {
         self.bb_occ ^ Bitboard::all()
     }
 
+    /// 空白マスを昇順に列挙する。
+    pub fn empty_squares(&self) -> impl Iterator<Item = Square> {
+        self.bb_blank().squares()
+    }
+
+    /// 縦、横、斜めのいずれかに並んだ 2 つのマス `sq1`, `sq2` の間(両端を含まない)に駒がないかどうかを返す。
+    /// 2 つのマスが並んでいない場合、`false` を返す。
+    ///
+    /// 香、角、飛車などの移動の合法性判定に使える。
+    pub fn path_is_clear(&self, sq1: Square, sq2: Square) -> bool {
+        if DirectionSet::from_squares(sq1, sq2).is_empty() {
+            return false;
+        }
+
+        (bbs::between(sq1, sq2) & self.bb_occ).is_zero()
+    }
+
+    /// `side` 側の盤上の駒のうち、マス `dst` へ移動できるものを全て返す。
+    ///
+    /// 自殺手かどうかは考慮しない。成れるかどうかも問わない。
+    /// `dst` に `side` 側の駒がある場合、空の bitboard を返す。
+    pub fn movers_to(&self, side: Side, dst: Square) -> Bitboard {
+        if self.bb_occ_side[side].test_square(dst) {
+            return Bitboard::zero();
+        }
+
+        // dst に相手側の駒を置いたときの利きを求め、それと自駒の AND をとる。
+        let them = side.inv();
+        let occ = self.bb_occ;
+
+        let bb_gold = self.bb_pk[GOLD]
+            | self.bb_pk[PRO_PAWN]
+            | self.bb_pk[PRO_LANCE]
+            | self.bb_pk[PRO_KNIGHT]
+            | self.bb_pk[PRO_SILVER];
+        let bb_rook = self.bb_pk[ROOK] | self.bb_pk[DRAGON];
+        let bb_bishop = self.bb_pk[BISHOP] | self.bb_pk[HORSE];
+        let bb_king = self.bb_pk[KING] | self.bb_pk[HORSE] | self.bb_pk[DRAGON];
+
+        let bb = (bbs::pawn_effect(them, dst) & self.bb_pk[PAWN])
+            | (bbs::lance_effect(them, dst, occ) & self.bb_pk[LANCE])
+            | (bbs::knight_effect(them, dst) & self.bb_pk[KNIGHT])
+            | (bbs::silver_effect(them, dst) & self.bb_pk[SILVER])
+            | (bbs::gold_effect(them, dst) & bb_gold)
+            | (bbs::bishop_effect(dst, occ) & bb_bishop)
+            | (bbs::rook_effect(dst, occ) & bb_rook)
+            | (bbs::king_effect(dst) & bb_king);
+
+        bb & self.bb_occ_side[side]
+    }
+
+    /// 盤上の駒を動かす指し手 `mv` について、成りが可能かどうかを返す。
+    ///
+    /// `mv` の成りフラグは無視される。移動元には駒がなければならない。
+    /// 駒の動きとして正しいかどうかはチェックしない。
+    pub fn promotion_status(&self, mv: Move) -> PromotionStatus {
+        debug_assert!(!mv.is_drop());
+
+        let src = mv.src();
+        let dst = mv.dst();
+        let pc = self.board[src];
+        debug_assert!(pc.is_piece());
+
+        let side = pc.side();
+
+        // 成れない駒、および移動元と移動先のいずれも敵陣でない場合は成れない。
+        if !pc.is_promotable() || !(src.is_promotion_zone(side) || dst.is_promotion_zone(side)) {
+            return PromotionStatus::None;
+        }
+
+        // 歩、香は敵陣 1 段目、桂は敵陣 1, 2 段目へ不成で移動できない。
+        let row = dst.row();
+        let deadend = match (pc.kind(), side) {
+            (PAWN | LANCE, HUM) => row == ROW_1,
+            (PAWN | LANCE, _) => row == ROW_9,
+            (KNIGHT, HUM) => row <= ROW_2,
+            (KNIGHT, _) => row >= ROW_8,
+            _ => false,
+        };
+
+        if deadend {
+            PromotionStatus::Mandatory
+        } else {
+            PromotionStatus::Optional
+        }
+    }
+
     /// 指定した陣営の `EffectCountBoard` への参照を返す。
     pub fn effect_count_board(&self, side: Side) -> &EffectCountBoard {
         &self.effect_counts[side]
     }
 
+    /// 指定した陣営の利きがあるマス全体を表す bitboard を返す。
+    ///
+    /// 利き数が 1 以上のマスの集合であり、利き数盤面を走査して求める。
+    pub fn attack_map(&self, side: Side) -> Bitboard {
+        let effect_count = &self.effect_counts[side];
+
+        Square::iter()
+            .filter(|&sq| effect_count[sq] > 0)
+            .fold(Bitboard::zero(), |bb, sq| bb | bbs::square(sq))
+    }
+
+    /// `moving_from` にある駒がそこから動いた場合に、新たに敵玉へ王手をかける `side` 側の遠隔駒たちを返す。
+    ///
+    /// 敵玉、`moving_from`、遠隔駒がこの順に一直線に並び、間に他の駒がないものが該当する(開き王手)。
+    /// `moving_from` の駒の移動先は考慮しないので、同じ直線上を動く場合は実際には王手にならない。
+    pub fn discovered_checkers(&self, side: Side, moving_from: Square) -> Bitboard {
+        let king_sq = self.king_sq[side.inv()];
+        let occ = bbs::square(moving_from).andnot(self.bb_occ);
+
+        let bb_rook = self.bb_piece(side, ROOK) | self.bb_piece(side, DRAGON);
+        let bb_bishop = self.bb_piece(side, BISHOP) | self.bb_piece(side, HORSE);
+        let bb_lance = self.bb_piece(side, LANCE);
+
+        let bb_checker = (bbs::rook_effect(king_sq, occ) & bb_rook)
+            | (bbs::bishop_effect(king_sq, occ) & bb_bishop)
+            | (bbs::lance_effect(side.inv(), king_sq, occ) & bb_lance);
+
+        bb_checker
+            .squares()
+            .filter(|&sq| bbs::between(king_sq, sq).test_square(moving_from))
+            .fold(Bitboard::zero(), |bb, sq| bb | bbs::square(sq))
+    }
+
     /// 指定した陣営の玉位置を返す。
     pub fn king_square(&self, side: Side) -> Square {
         self.king_sq[side]
     }
 
+    /// 両陣営について、保持している玉位置に実際にその陣営の玉があり、かつ盤上の玉がちょうど 1 枚であることを確認する。
+    ///
+    /// `Position::new()` は両陣営とも玉が存在すると仮定しているので、手作業で作った局面のデバッグに使う。
+    pub fn verify_king_squares(&self) -> anyhow::Result<()> {
+        for side in [HUM, COM] {
+            let sq = self.king_sq[side];
+            let pc = self.board[sq];
+            ensure!(
+                pc == Piece::new(side, KING),
+                "{} king square is {}, but the piece there is {:?}",
+                side,
+                sq,
+                pc
+            );
+
+            let count = self.bb_piece(side, KING).count_ones();
+            ensure!(count == 1, "{} has {} kings on board", side, count);
+        }
+
+        Ok(())
+    }
+
     /// COM 側の玉以外の駒数(盤上の駒と手駒の合計)を返す。
     /// 全駒勝利手順を求める際の枝刈りに使う。
     pub fn com_nonking_count(&self) -> u32 {
         self.com_nonking_count
     }
 
+    /// COM 側の玉以外の駒数を盤面と手駒から数え直して返す。
+    ///
+    /// 差分計算される `com_nonking_count()` の検証用。
+    pub fn recompute_com_nonking_count(&self) -> u32 {
+        self.nonking_count(COM)
+    }
+
+    /// 指定した陣営の玉以外の駒数(盤上の駒と手駒の合計)を返す。
+    ///
+    /// `com_nonking_count()` と異なり差分計算はしておらず、呼び出しのたびに数え直す。
+    pub fn nonking_count(&self, side: Side) -> u32 {
+        let on_board = (self.bb_occ_side[side] & !self.bb_pk[KING]).count_ones();
+        let in_hand: u32 = self.hands[side].iter().map(|(_, n)| n).sum();
+
+        on_board + in_hand
+    }
+
+    /// 指定した陣営の行きどころのない駒(以後動けない駒)を表す bitboard を返す。
+    ///
+    /// 敵陣 1 段目の歩・香と、敵陣 1, 2 段目の桂が該当する。これらがある局面は不正である。
+    pub fn dead_pieces(&self, side: Side) -> Bitboard {
+        // 駒打ちの指し手生成と同じく、行きどころのある段を表すマスクを用いる。
+        let (bb_mask_1, bb_mask_2) = if side == HUM {
+            (bbs::forward_rows(COM, ROW_1), bbs::forward_rows(COM, ROW_2))
+        } else {
+            (bbs::forward_rows(HUM, ROW_9), bbs::forward_rows(HUM, ROW_8))
+        };
+
+        let bb_pawn_lance = self.bb_piece(side, PAWN) | self.bb_piece(side, LANCE);
+        let bb_knight = self.bb_piece(side, KNIGHT);
+
+        bb_mask_1.andnot(bb_pawn_lance) | bb_mask_2.andnot(bb_knight)
+    }
+
+    /// 指定した陣営が裸玉(盤上に玉のみがあり、手駒もない)かどうかを返す。
+    pub fn bare_king(&self, side: Side) -> bool {
+        (self.bb_occ_side[side] & !self.bb_pk[KING]).is_zero() && self.hands[side].is_empty()
+    }
+
+    /// 指定した陣営が相手の玉以外の駒を全て取った(全駒)かどうかを返す。
+    pub fn all_captured_by(&self, side: Side) -> bool {
+        self.bare_king(side.inv())
+    }
+
     /// 指し手で局面を進め、`UndoableMove` を返す。
     ///
     /// `mv` は少なくとも疑似合法手であり、かつ玉を取る手ではないと仮定している。
@@ -196,37 +612,11 @@ impl Position {
 
         self.side_to_move = self.side_to_move.inv();
         self.ply += 1;
+        self.debug_check_turn_parity();
+        debug_assert_eq!(self.com_nonking_count, self.recompute_com_nonking_count());
 
-        /* for debug
-        {
-            let (effect_counts, ranged_effects) = calc_effect(self);
-            let ng_count_hum = self.effect_counts[HUM] != effect_counts[HUM];
-            let ng_count_com = self.effect_counts[COM] != effect_counts[COM];
-            let ng_range = self.ranged_effects != ranged_effects;
-            let ng = ng_count_hum || ng_count_com || ng_range;
-            if ng {
-                eprintln!("[do_move() failed]");
-                eprintln!("{}", self);
-                eprintln!("指し手: {}", umv);
-            }
-            if ng_count_hum {
-                eprintln!("--- HUM counts ---");
-                eprintln!("{}", self.effect_counts[HUM]);
-                eprintln!("{}", effect_counts[HUM]);
-            }
-            if ng_count_com {
-                eprintln!("--- COM counts ---");
-                eprintln!("{}", self.effect_counts[COM]);
-                eprintln!("{}", effect_counts[COM]);
-            }
-            if ng_range {
-                eprintln!("--- ranged ---");
-                eprintln!("{}", self.ranged_effects);
-                eprintln!("{}", ranged_effects);
-            }
-            assert!(!ng);
-        }
-        */
+        #[cfg(debug_assertions)]
+        self.debug_check_effects("do_move", umv);
 
         umv
     }
@@ -552,7 +942,8 @@ impl Position {
         debug_assert!(self.hands[us][pk] > 0);
 
         // 打つ駒種を手駒から減らす。
-        self.hands[us][pk] -= 1;
+        let result = self.hands[us].try_decrement(pk);
+        debug_assert!(result.is_some(), "do_move_drop(): hand has no {:?}", pk);
 
         // pk を自駒として移動先に置く。
         self.put_piece(dst, Piece::new(us, pk));
@@ -713,6 +1104,156 @@ impl Position {
         }
     }
 
+    /// 指し手たちで順に局面を進め、`UndoableMove` の配列を返す。
+    ///
+    /// 各指し手について、手番側の駒を動かす(打つ)手であること、自駒や玉を取る手でないことを確認する。
+    /// 確認に失敗した場合、それまでに指した手を全て undo し、局面を元に戻してからエラーを返す。
+    /// それ以外の合法性チェックは行わない。
+    pub fn apply_moves(&mut self, mvs: &[Move]) -> anyhow::Result<Vec<UndoableMove>> {
+        let mut umvs = Vec::with_capacity(mvs.len());
+
+        for (i, &mv) in mvs.iter().enumerate() {
+            if let Err(e) = self.check_move_shape(mv) {
+                self.undo_moves(&umvs);
+                return Err(e.context(format!("invalid move at index {}: {}", i, mv)));
+            }
+            umvs.push(self.do_move(mv));
+        }
+
+        Ok(umvs)
+    }
+
+    /// `apply_moves()` が返した `UndoableMove` の配列を逆順に undo する。
+    pub fn undo_moves(&mut self, umvs: &[UndoableMove]) {
+        for &umv in umvs.iter().rev() {
+            self.undo_move(umv);
+        }
+    }
+
+    /// 棋譜などから復元した `UndoableMove` が現在の局面と整合するかどうかを返す。
+    ///
+    /// 盤上の駒を動かす手の場合、移動元の駒が手番の側の `piece_src()` であり、
+    /// かつ移動先の駒が `piece_captured()` であることを確認する。
+    /// 駒打ちの場合、手番の側がその駒を持っており、かつ移動先が空白であることを確認する。
+    /// 指し手自体の合法性(駒の動き方など)は確認しない。
+    pub fn undoable_matches_board(&self, umv: UndoableMove) -> bool {
+        if !umv.is_valid() {
+            return false;
+        }
+
+        let us = self.side_to_move;
+
+        if umv.is_drop() {
+            self.hands[us][umv.dropped_piece_kind()] > 0 && self.board[umv.dst()] == NO_PIECE
+        } else {
+            let pc_src = umv.piece_src();
+            pc_src.side() == us
+                && self.board[umv.src()] == pc_src
+                && self.board[umv.dst()] == umv.piece_captured()
+        }
+    }
+
+    /// `apply_moves()` 用の指し手の簡易チェック。
+    fn check_move_shape(&self, mv: Move) -> anyhow::Result<()> {
+        let us = self.side_to_move;
+        let pc_dst = self.board[mv.dst()];
+
+        if mv.is_drop() {
+            let pk = mv.dropped_piece_kind();
+            ensure!(self.hands[us][pk] > 0, "{} has no {:?} in hand", us, pk);
+            ensure!(pc_dst == NO_PIECE, "drop destination is occupied");
+        } else {
+            let pc_src = self.board[mv.src()];
+            ensure!(
+                pc_src.is_piece() && pc_src.side() == us,
+                "source is not a piece of {}",
+                us
+            );
+            ensure!(
+                pc_dst == NO_PIECE || (pc_dst.side() != us && pc_dst.kind() != KING),
+                "destination has own piece or king"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// 現在の局面をチェックポイントとする `PositionGuard` を返す。
+    ///
+    /// ガードを通して指した手は、ガードの drop 時または `PositionGuard::rollback()` で全て undo される。
+    pub fn checkpoint(&mut self) -> PositionGuard<'_> {
+        PositionGuard {
+            pos: self,
+            umvs: Vec::new(),
+        }
+    }
+
+    /// `do_move()` と同様に指し手で局面を進め、盤面と手駒の変化を `delta` に通知する。
+    ///
+    /// 通知のないときのコストを避けるため、`do_move()` とは別関数としている。
+    pub fn do_move_with_delta<D: BoardDelta>(&mut self, mv: Move, delta: &mut D) -> UndoableMove {
+        let us = self.side_to_move;
+
+        let umv = self.do_move(mv);
+
+        if umv.is_drop() {
+            let pk = umv.dropped_piece_kind();
+            delta.change_hand(us, pk, -1);
+            delta.put(umv.dst(), Piece::new(us, pk));
+        } else {
+            let pc_captured = umv.piece_captured();
+            delta.remove(umv.src(), umv.piece_src());
+            if pc_captured != NO_PIECE {
+                delta.remove(umv.dst(), pc_captured);
+                delta.change_hand(us, pc_captured.to_raw_kind(), 1);
+            }
+            delta.put(umv.dst(), umv.piece_dst());
+        }
+
+        umv
+    }
+
+    /// `undo_move()` と同様に指し手を undo し、盤面と手駒の変化を `delta` に通知する。
+    pub fn undo_move_with_delta<D: BoardDelta>(&mut self, umv: UndoableMove, delta: &mut D) {
+        self.undo_move(umv);
+
+        let us = self.side_to_move;
+
+        if umv.is_drop() {
+            let pk = umv.dropped_piece_kind();
+            delta.remove(umv.dst(), Piece::new(us, pk));
+            delta.change_hand(us, pk, 1);
+        } else {
+            let pc_captured = umv.piece_captured();
+            delta.remove(umv.dst(), umv.piece_dst());
+            if pc_captured != NO_PIECE {
+                delta.change_hand(us, pc_captured.to_raw_kind(), -1);
+                delta.put(umv.dst(), pc_captured);
+            }
+            delta.put(umv.src(), umv.piece_src());
+        }
+    }
+
+    /// 盤面と手駒を変えずに手番だけを進める(null move)。
+    ///
+    /// 通常の対局では現れない操作で、デバッグビルドでのみ使える。手番側が王手をかけられていないことは確認しない。
+    #[cfg(debug_assertions)]
+    pub(crate) fn do_null_move(&mut self) {
+        self.side_to_move = self.side_to_move.inv();
+        self.ply += 1;
+        self.debug_check_turn_parity();
+    }
+
+    /// `do_null_move()` を undo する。
+    #[cfg(all(test, debug_assertions))]
+    pub(crate) fn undo_null_move(&mut self) {
+        debug_assert!(self.ply > 1);
+
+        self.side_to_move = self.side_to_move.inv();
+        self.ply -= 1;
+        self.debug_check_turn_parity();
+    }
+
     /// 指し手を undo する。
     ///
     /// 不正な指し手は渡されないと仮定している。
@@ -722,43 +1263,17 @@ impl Position {
         // 先に手数と手番を戻す。この方が局面の手番と指し手の主体が一致するのでわかりやすいと思う。
         self.side_to_move = self.side_to_move.inv();
         self.ply -= 1;
+        self.debug_check_turn_parity();
 
         if umv.is_drop() {
             self.undo_move_drop(umv);
         } else {
             self.undo_move_walk(umv);
         }
+        debug_assert_eq!(self.com_nonking_count, self.recompute_com_nonking_count());
 
-        /* for debug
-        {
-            let (effect_counts, ranged_effects) = calc_effect(self);
-            let ng_count_hum = self.effect_counts[HUM] != effect_counts[HUM];
-            let ng_count_com = self.effect_counts[COM] != effect_counts[COM];
-            let ng_range = self.ranged_effects != ranged_effects;
-            let ng = ng_count_hum || ng_count_com || ng_range;
-            if ng {
-                eprintln!("[undo_move() failed]");
-                eprintln!("{}", self);
-                eprintln!("指し手: {}", umv);
-            }
-            if ng_count_hum {
-                eprintln!("--- HUM counts ---");
-                eprintln!("{}", self.effect_counts[HUM]);
-                eprintln!("{}", effect_counts[HUM]);
-            }
-            if ng_count_com {
-                eprintln!("--- COM counts ---");
-                eprintln!("{}", self.effect_counts[COM]);
-                eprintln!("{}", effect_counts[COM]);
-            }
-            if ng_range {
-                eprintln!("--- ranged ---");
-                eprintln!("{}", self.ranged_effects);
-                eprintln!("{}", ranged_effects);
-            }
-            assert!(!ng);
-        }
-        */
+        #[cfg(debug_assertions)]
+        self.debug_check_effects("undo_move", umv);
     }
 
     /// 盤上の駒を動かす指し手を undo する。
@@ -801,7 +1316,12 @@ impl Position {
 
             // 捕獲した駒を us 側の手駒から減らす。
             let pr_captured = pc_captured.to_raw_kind();
-            self.hands[us][pr_captured] -= 1;
+            let result = self.hands[us].try_decrement(pr_captured);
+            debug_assert!(
+                result.is_some(),
+                "undo_move_walk(): hand has no {:?}",
+                pr_captured
+            );
 
             // 利き情報を復元。
             self.revert_effect_by_capture(src, dst, pc_src, pc_dst, pc_captured);
@@ -1135,6 +1655,14 @@ impl Position {
         self.effect_counts[them][self.king_sq[us]] > 0
     }
 
+    /// 手番の側が王手をかけられているかどうかを返す。`is_checked(side_to_move())` と同じ。
+    ///
+    /// 利き数は差分更新されているので、これは玉位置の利き数を読むだけで済む。
+    /// よって王手フラグを別途キャッシュすることはしていない。
+    pub fn side_to_move_in_check(&self) -> bool {
+        self.is_checked(self.side_to_move)
+    }
+
     /// 手番の側がチェックメイト(**打ち歩含む**)されているかどうかを返す。
     ///
     /// 関数から戻ったとき、`self` は呼び出し前の局面に戻っている。
@@ -1142,6 +1670,106 @@ impl Position {
         position_is_checkmated(self)
     }
 
+    /// COM 側の合法手を原作の生成順に 1 手ずつ `f` に渡す。`f` が `ControlFlow::Break` を返したら打ち切る。
+    ///
+    /// 自殺手と打ち歩詰めは除かれる。指し手はマス単位で生成し、合法性判定は `f` に渡す直前に 1 手ずつ行うので、
+    /// 全指し手の配列は作らず、早期に打ち切れば残りの指し手の生成・判定コストはかからない。
+    /// 局面は COM の手番であることを仮定している。
+    ///
+    /// 関数から戻ったとき、`self` は呼び出し前の局面に戻っている。
+    pub fn for_each_legal_com<F>(&mut self, mut f: F)
+    where
+        F: FnMut(Move) -> ControlFlow<()>,
+    {
+        debug_assert_eq!(self.side_to_move, COM);
+
+        let gen = ComMoveGenerator::new(self);
+        let mut mvs = SquareMoveArray::new();
+
+        for sq in naitou_squares() {
+            mvs.clear();
+            gen.generate_square(self, sq, &mut mvs);
+
+            // is_legal() は局面を元に戻して返るので、次のマスの生成には影響しない。
+            for &mv in &mvs {
+                if !self.is_legal(mv) {
+                    continue;
+                }
+                if f(mv).is_break() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// 手番の側の全ての合法手を生成する。HUM, COM どちらの手番でもよい。
+    ///
+    /// 疑似合法手(行きどころのない駒や二歩は既に除かれている)から自殺手と打ち歩詰めを除いたものを返す。
+    /// 生成順は未規定。COM の思考に使う原作通りの生成順が必要なら `for_each_legal_com()` を使うこと。
+    ///
+    /// 関数から戻ったとき、`self` は呼び出し前の局面に戻っている。
+    pub fn legal_moves(&mut self) -> MoveArray {
+        let mvs = if self.is_checked(self.side_to_move) {
+            generate_evasions(self)
+        } else {
+            generate_moves(self)
+        };
+
+        mvs.into_iter().filter(|&mv| self.is_legal(mv)).collect()
+    }
+
+    /// マス `sq` にある手番の側の駒を合法に移動できるマスたちを返す。成/不成は区別しない。
+    ///
+    /// `sq` に手番の側の駒がない場合、空の bitboard を返す。
+    ///
+    /// 関数から戻ったとき、`self` は呼び出し前の局面に戻っている。
+    pub fn legal_destinations(&mut self, sq: Square) -> Bitboard {
+        let pc = self.board[sq];
+        if pc == NO_PIECE || pc.side() != self.side_to_move {
+            return Bitboard::zero();
+        }
+
+        self.legal_moves()
+            .into_iter()
+            .filter(|mv| !mv.is_drop() && mv.src() == sq)
+            .fold(Bitboard::zero(), |bb, mv| bb | bbs::square(mv.dst()))
+    }
+
+    /// `side` 側の疑似合法手の数(機動力)を返す。手番は問わない。
+    ///
+    /// 速度のため合法性の判定は行わないので、自殺手や打ち歩詰めも数に含まれる。
+    /// 合法手の数が必要なら `legal_moves()` を使うこと。
+    ///
+    /// 関数から戻ったとき、`self` は呼び出し前の局面に戻っている。
+    pub fn mobility(&mut self, side: Side) -> u32 {
+        let side_to_move = self.side_to_move;
+
+        self.side_to_move = side;
+        let n = generate_moves(self).len();
+        self.side_to_move = side_to_move;
+
+        n as u32
+    }
+
+    /// 手番の側の疑似合法手 `mv` が合法(自殺手でも打ち歩詰めでもない)かどうかを返す。
+    fn is_legal(&mut self, mv: Move) -> bool {
+        let us = self.side_to_move;
+
+        let umv = self.do_move(mv);
+
+        let legal = if self.is_checked(us) {
+            false
+        } else if mv.is_drop() && mv.dropped_piece_kind() == PAWN && self.is_checked(us.inv()) {
+            !self.is_checkmated()
+        } else {
+            true
+        };
+
+        self.undo_move(umv);
+
+        legal
+    }
+
     /// `sq` に `pc` を置き、bitboard たちも合わせて更新する。
     /// `sq` は空白でなければならない。
     fn put_piece(&mut self, sq: Square, pc: Piece) {
@@ -1180,8 +1808,58 @@ impl std::fmt::Display for Position {
     }
 }
 
+/// `Position::checkpoint()` が返すガード。
+///
+/// ガードを通して指した手を記録しておき、drop 時または `rollback()` でそれらを逆順に undo する。
+/// 局面の参照は `Deref` で行える。ガードを通さずに局面を変更することはできない。
+#[derive(Debug)]
+pub struct PositionGuard<'a> {
+    pos: &'a mut Position,
+    umvs: Vec<UndoableMove>, // チェックポイント以降に指した手
+}
+
+impl PositionGuard<'_> {
+    /// 指し手で局面を進め、undo 用に記録する。`Position::do_move()` を参照。
+    pub fn do_move(&mut self, mv: Move) -> UndoableMove {
+        let umv = self.pos.do_move(mv);
+        self.umvs.push(umv);
+        umv
+    }
+
+    /// 現在の局面をチェックポイントとする入れ子のガードを返す。
+    ///
+    /// 入れ子のガードを通して指した手は、入れ子のガードの drop 時に undo される。
+    pub fn checkpoint(&mut self) -> PositionGuard<'_> {
+        self.pos.checkpoint()
+    }
+
+    /// チェックポイント以降に指した手数を返す。
+    pub fn move_count(&self) -> usize {
+        self.umvs.len()
+    }
+
+    /// チェックポイントの局面に戻す。drop と同じだが、明示的に呼びたい場合に使う。
+    pub fn rollback(self) {}
+}
+
+impl std::ops::Deref for PositionGuard<'_> {
+    type Target = Position;
+
+    fn deref(&self) -> &Position {
+        self.pos
+    }
+}
+
+impl Drop for PositionGuard<'_> {
+    fn drop(&mut self) {
+        while let Some(umv) = self.umvs.pop() {
+            self.pos.undo_move(umv);
+        }
+    }
+}
+
 /// 局面から `EffectCountBoards`, `RangedEffectBoard` を愚直に計算する。
-/// `Position` の初期化時のみ使う。
+/// `Position` の初期化時、および利きの自己検査時のみ使う。
 fn calc_effect(pos: &Position) -> (EffectCountBoards, RangedEffectBoard) {
     let mut effect_counts =
         EffectCountBoards::from([EffectCountBoard::empty(), EffectCountBoard::empty()]);
@@ -1254,3 +1932,774 @@ fn calc_effect(pos: &Position) -> (EffectCountBoards, RangedEffectBoard) {
 
     (effect_counts, ranged_effects)
 }
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    use crate::naitou::Handicap;
+    use crate::sfen::{sfen_decode_move, sfen_decode_moves, sfen_decode_position};
+
+    use super::*;
+
+    fn sfen_to_position(sfen: &str) -> Position {
+        let (side_to_move, board, hands) = sfen_decode_position(sfen).unwrap();
+
+        Position::new(side_to_move, board, hands)
+    }
+
+    #[test]
+    fn test_flipped() {
+        let pos = sfen_to_position(
+            "sfen l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1",
+        );
+
+        let flipped = pos.flipped();
+        assert_eq!(flipped.side_to_move(), HUM);
+        assert_eq!(flipped.hand(HUM), pos.hand(COM));
+        assert_eq!(flipped.hand(COM), pos.hand(HUM));
+        for side in Side::iter() {
+            let sq = pos.king_square(side);
+            assert_eq!(
+                flipped.king_square(side.inv()),
+                Square::from_inner(80 - sq.inner())
+            );
+        }
+
+        assert!(flipped.flipped().same_position(&pos));
+        assert!(!flipped.same_position(&pos));
+    }
+
+    #[test]
+    fn test_hands_iter() {
+        let pos = sfen_to_position("sfen 4k4/9/9/9/9/9/9/9/4K4 b R2Pbg3p 1");
+
+        assert_eq!(
+            pos.hands_iter().collect::<Vec<_>>(),
+            [
+                (HUM, PAWN, 2),
+                (HUM, ROOK, 1),
+                (COM, PAWN, 3),
+                (COM, BISHOP, 1),
+                (COM, GOLD, 1),
+            ]
+        );
+
+        assert_eq!(sfen_to_position("startpos").hands_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_hand_kinds() {
+        let pos = sfen_to_position("sfen 4k4/9/9/9/9/9/9/9/4K4 b R2PSbg3p 1");
+
+        assert_eq!(
+            pos.hand_kinds(HUM).collect::<Vec<_>>(),
+            [PAWN, SILVER, ROOK]
+        );
+        assert_eq!(
+            pos.hand_kinds(COM).collect::<Vec<_>>(),
+            [PAWN, BISHOP, GOLD]
+        );
+
+        assert_eq!(sfen_to_position("startpos").hand_kinds(HUM).count(), 0);
+    }
+
+    #[test]
+    fn test_board_delta() {
+        /// 通知されたイベントを盤面と手駒に反映する。
+        struct Mirror {
+            board: Board,
+            hands: Hands,
+        }
+
+        impl BoardDelta for Mirror {
+            fn put(&mut self, sq: Square, pc: Piece) {
+                assert_eq!(self.board[sq], NO_PIECE);
+                self.board[sq] = pc;
+            }
+
+            fn remove(&mut self, sq: Square, pc: Piece) {
+                assert_eq!(self.board[sq], pc);
+                self.board[sq] = NO_PIECE;
+            }
+
+            fn change_hand(&mut self, side: Side, pk: PieceKind, delta: i32) {
+                let n = &mut self.hands[side][pk];
+                *n = (*n as i32 + delta) as u32;
+            }
+        }
+
+        const SFEN: &str =
+            "sfen lnsgkgsnl/1r5b1/pppppp1pp/6p2/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL b - 1";
+
+        let mut pos = sfen_to_position(SFEN);
+        let mut mirror = Mirror {
+            board: pos.board().clone(),
+            hands: *pos.hands(),
+        };
+
+        // 駒取りかつ成りの指し手。
+        let umv = pos.do_move_with_delta(Move::new_walk_promotion(SQ_88, SQ_22), &mut mirror);
+        assert_eq!(&mirror.board, pos.board());
+        assert_eq!(&mirror.hands, pos.hands());
+        assert_eq!(pos.hand(HUM)[BISHOP], 1);
+
+        // 成駒を取り返す指し手。
+        let umv_recapture = pos.do_move_with_delta(Move::new_walk(SQ_31, SQ_22), &mut mirror);
+        assert_eq!(&mirror.board, pos.board());
+        assert_eq!(&mirror.hands, pos.hands());
+        assert_eq!(pos.hand(COM)[BISHOP], 1);
+
+        // 駒打ち。
+        let umv_drop = pos.do_move_with_delta(Move::new_drop(BISHOP, SQ_55), &mut mirror);
+        assert_eq!(&mirror.board, pos.board());
+        assert_eq!(&mirror.hands, pos.hands());
+
+        pos.undo_move_with_delta(umv_drop, &mut mirror);
+        pos.undo_move_with_delta(umv_recapture, &mut mirror);
+        pos.undo_move_with_delta(umv, &mut mirror);
+        assert_eq!(&mirror.board, pos.board());
+        assert_eq!(&mirror.hands, pos.hands());
+        assert!(pos.same_position(&sfen_to_position(SFEN)));
+    }
+
+    #[test]
+    fn test_path_is_clear() {
+        let pos = sfen_to_position("startpos");
+
+        // 角道は７七の歩で塞がっている。
+        assert!(!pos.path_is_clear(SQ_88, SQ_22));
+
+        let pos = sfen_to_position(
+            "sfen lnsgkgsnl/1r5b1/pppppp1pp/6p2/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL b - 1",
+        );
+
+        // 角道が開いている。
+        assert!(pos.path_is_clear(SQ_88, SQ_22));
+        assert!(pos.path_is_clear(SQ_22, SQ_88));
+        // 隣接するマス。
+        assert!(pos.path_is_clear(SQ_88, SQ_77));
+        // 飛車の前は歩で塞がっている。
+        assert!(!pos.path_is_clear(SQ_28, SQ_24));
+        // 並んでいないマス。
+        assert!(!pos.path_is_clear(SQ_88, SQ_23));
+        assert!(!pos.path_is_clear(SQ_55, SQ_55));
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        const SFEN: &str =
+            "sfen lnsgkgsnl/1r5b1/pppppp1pp/6p2/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL b - 1";
+
+        let mut pos = sfen_to_position(SFEN);
+        let orig = pos.clone();
+
+        {
+            let mut guard = pos.checkpoint();
+            guard.do_move(Move::new_walk_promotion(SQ_88, SQ_22));
+            guard.do_move(Move::new_walk(SQ_31, SQ_22));
+            let after2 = guard.clone();
+
+            {
+                let mut inner = guard.checkpoint();
+                inner.do_move(Move::new_drop(BISHOP, SQ_55));
+                inner.do_move(Move::new_drop(BISHOP, SQ_45));
+                assert_eq!(inner.move_count(), 2);
+                assert_eq!(inner.ply(), 5);
+            }
+
+            // 入れ子のガードの分だけ戻る。
+            assert_eq!(guard.move_count(), 2);
+            assert!(guard.same_position(&after2));
+            assert_eq!(guard.ply(), after2.ply());
+            assert_eq!(
+                guard.effect_count_board(HUM),
+                after2.effect_count_board(HUM)
+            );
+            assert_eq!(
+                guard.effect_count_board(COM),
+                after2.effect_count_board(COM)
+            );
+
+            guard.do_move(Move::new_walk(SQ_27, SQ_26));
+            guard.rollback();
+        }
+
+        assert!(pos.same_position(&orig));
+        assert_eq!(pos.ply(), orig.ply());
+        assert_eq!(pos.effect_count_board(HUM), orig.effect_count_board(HUM));
+        assert_eq!(pos.effect_count_board(COM), orig.effect_count_board(COM));
+    }
+
+    #[test]
+    fn test_movers_to() {
+        let pos = sfen_to_position("startpos");
+
+        // ３八には銀、金、飛車が動ける。
+        assert_eq!(
+            pos.movers_to(HUM, SQ_38),
+            Bitboard::from(SQ_39) | Bitboard::from(SQ_49) | Bitboard::from(SQ_28)
+        );
+
+        // 自駒のあるマス。
+        assert_eq!(pos.movers_to(HUM, SQ_77), Bitboard::zero());
+
+        // 両方の銀が５五に動ける。
+        let pos = sfen_to_position("sfen 4k4/9/9/9/9/3S1S3/9/1B7/4K4 b - 1");
+        assert_eq!(
+            pos.movers_to(HUM, SQ_55),
+            Bitboard::from(SQ_66) | Bitboard::from(SQ_46)
+        );
+        assert_eq!(pos.movers_to(COM, SQ_55), Bitboard::zero());
+
+        // 飛び駒は遮られる。角は７七に利くが、６六の銀の先には利かない。
+        assert_eq!(
+            pos.movers_to(HUM, SQ_77),
+            Bitboard::from(SQ_88) | Bitboard::from(SQ_66)
+        );
+        assert_eq!(pos.movers_to(HUM, SQ_44), Bitboard::zero());
+
+        // 香の前方への移動。
+        let pos = sfen_to_position("sfen 4k4/9/9/9/9/9/9/9/L3K4 b - 1");
+        assert_eq!(pos.movers_to(HUM, SQ_91), Bitboard::from(SQ_99));
+        assert_eq!(pos.movers_to(HUM, SQ_98), Bitboard::from(SQ_99));
+    }
+
+    #[test]
+    fn test_bare_king() {
+        let pos = sfen_to_position("startpos");
+        assert!(!pos.bare_king(HUM));
+        assert!(!pos.bare_king(COM));
+        assert_eq!(pos.nonking_count(HUM), 19);
+        assert_eq!(pos.nonking_count(COM), pos.com_nonking_count());
+
+        // COM は玉のみ。
+        let pos = sfen_to_position("sfen 4k4/9/9/9/9/9/9/9/4K4 b RBG 1");
+        assert!(pos.bare_king(COM));
+        assert!(pos.all_captured_by(HUM));
+        assert!(!pos.bare_king(HUM));
+        assert!(!pos.all_captured_by(COM));
+        assert_eq!(pos.nonking_count(COM), 0);
+        assert_eq!(pos.nonking_count(HUM), 3);
+
+        // COM に手駒があれば裸玉ではない。
+        let pos = sfen_to_position("sfen 4k4/9/9/9/9/9/9/9/4K4 b Rp 1");
+        assert!(!pos.bare_king(COM));
+        assert_eq!(pos.nonking_count(COM), pos.com_nonking_count());
+    }
+
+    #[test]
+    fn test_promotion_status() {
+        let pos = sfen_to_position("sfen 4k4/1P7/2N6/P8/9/4P4/5n3/9/4K4 b - 1");
+
+        // 歩: 敵陣 1 段目へは成り必須、敵陣 2 段目以前へは任意、敵陣外では成れない。
+        assert_eq!(
+            pos.promotion_status(Move::new_walk(SQ_82, SQ_81)),
+            PromotionStatus::Mandatory
+        );
+        assert_eq!(
+            pos.promotion_status(Move::new_walk(SQ_94, SQ_93)),
+            PromotionStatus::Optional
+        );
+        assert_eq!(
+            pos.promotion_status(Move::new_walk(SQ_56, SQ_55)),
+            PromotionStatus::None
+        );
+
+        // 桂: 敵陣 1, 2 段目へは成り必須。
+        assert_eq!(
+            pos.promotion_status(Move::new_walk(SQ_73, SQ_81)),
+            PromotionStatus::Mandatory
+        );
+        assert_eq!(
+            pos.promotion_status(Move::new_walk(SQ_47, SQ_39)),
+            PromotionStatus::Mandatory
+        );
+
+        // 敵陣から出る場合も成れる。
+        let pos = sfen_to_position("sfen 4k4/9/2N6/9/9/9/9/9/4K4 b - 1");
+        assert_eq!(
+            pos.promotion_status(Move::new_walk(SQ_73, SQ_65)),
+            PromotionStatus::Optional
+        );
+
+        // 金: 成れない。
+        let pos = sfen_to_position("sfen 4k4/9/3G5/9/9/9/9/9/4K4 b - 1");
+        assert_eq!(
+            pos.promotion_status(Move::new_walk(SQ_63, SQ_62)),
+            PromotionStatus::None
+        );
+    }
+
+    #[test]
+    fn test_verify_king_squares() {
+        let mut pos = sfen_to_position("startpos");
+        assert!(pos.verify_king_squares().is_ok());
+
+        // 保持している玉位置が盤面と食い違っている。
+        pos.king_sq[COM] = SQ_41;
+        assert!(pos.verify_king_squares().is_err());
+
+        // COM 玉が存在しない。
+        let pos = sfen_to_position("sfen 4p4/9/9/9/9/9/9/9/4K4 b - 1");
+        assert!(pos.verify_king_squares().is_err());
+
+        // HUM 玉が 2 枚ある。
+        let pos = sfen_to_position("sfen 4k4/9/9/9/9/9/9/9/3KK4 b - 1");
+        assert!(pos.verify_king_squares().is_err());
+    }
+
+    #[test]
+    fn test_for_each_legal_com() {
+        // COM 玉は５二の HUM 金に王手されている。合法手は玉で金を取る手のみ。
+        let mut pos = sfen_to_position("sfen 4k4/4G4/9/9/9/9/9/9/4K4 w - 1");
+        let mut mvs = Vec::new();
+        pos.for_each_legal_com(|mv| {
+            mvs.push(mv);
+            ControlFlow::Continue(())
+        });
+        assert_eq!(mvs, [Move::new_walk(SQ_51, SQ_52)]);
+
+        // 平手初期局面(COM 手番)の合法手は 30 手。
+        let mut pos = sfen_to_position(
+            "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+        );
+        let mut count = 0;
+        pos.for_each_legal_com(|_| {
+            count += 1;
+            ControlFlow::Continue(())
+        });
+        assert_eq!(count, 30);
+
+        // 早期に打ち切ると、それ以降 f は呼ばれない。
+        let mut count = 0;
+        pos.for_each_legal_com(|_| {
+            count += 1;
+            if count == 5 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert_eq!(count, 5);
+
+        // 打ち歩詰めは除かれる。
+        let mut pos = sfen_to_position("sfen k8/9/9/9/9/9/8g/7P1/7NK w p 1");
+        let mut mvs = Vec::new();
+        pos.for_each_legal_com(|mv| {
+            mvs.push(mv);
+            ControlFlow::Continue(())
+        });
+        assert!(!mvs.contains(&Move::new_drop(PAWN, SQ_18)));
+        assert!(mvs.contains(&Move::new_drop(PAWN, SQ_55)));
+
+        // 順序は generate_moves_com() から違法手を除いたものと一致する。
+        let mut pos = sfen_to_position(
+            "sfen l2gk2nl/1r1s2gb1/p1ppppspp/9/1p5P1/9/PPPPPPP1P/1BG4R1/LNS1KGSNL w Pp 1",
+        );
+        let mut mvs = Vec::new();
+        pos.for_each_legal_com(|mv| {
+            mvs.push(mv);
+            ControlFlow::Continue(())
+        });
+        let expected: Vec<_> = crate::movegen::generate_moves_com(&pos)
+            .into_iter()
+            .filter(|&mv| pos.is_legal(mv))
+            .collect();
+        assert_eq!(mvs, expected);
+    }
+
+    #[test]
+    fn test_turn_parity() {
+        // HUM が先に指す手合割。
+        {
+            let (side_to_move, board, hands) = Handicap::HumSenteSikenbisha.startpos();
+            let mut pos = Position::new(side_to_move, board, hands);
+            assert_eq!(pos.initial_side(), HUM);
+            assert_eq!(pos.ply(), 1);
+            assert!(pos.is_hum_turn());
+            pos.debug_check_turn_parity();
+
+            let umv = pos.do_move(sfen_decode_move("7g7f").unwrap());
+            assert_eq!(pos.ply(), 2);
+            assert!(!pos.is_hum_turn());
+            assert_eq!(pos.initial_side(), HUM);
+
+            pos.undo_move(umv);
+            assert!(pos.is_hum_turn());
+        }
+
+        // COM が先に指す手合割でも手数は 1 から始まり、手数が奇数なら COM の手番となる。
+        {
+            let (side_to_move, board, hands) = Handicap::ComHishaochi.startpos();
+            let mut pos = Position::new(side_to_move, board, hands);
+            assert_eq!(pos.initial_side(), COM);
+            assert_eq!(pos.ply(), 1);
+            assert!(!pos.is_hum_turn());
+            pos.debug_check_turn_parity();
+
+            pos.do_move(sfen_decode_move("3c3d").unwrap());
+            assert_eq!(pos.ply(), 2);
+            assert!(pos.is_hum_turn());
+
+            pos.do_move(sfen_decode_move("7g7f").unwrap());
+            assert_eq!(pos.ply(), 3);
+            assert!(!pos.is_hum_turn());
+            pos.debug_check_turn_parity();
+        }
+    }
+
+    #[test]
+    fn test_recompute_com_nonking_count() {
+        let mut pos = sfen_to_position("startpos");
+        assert_eq!(pos.com_nonking_count(), 19);
+        assert_eq!(pos.recompute_com_nonking_count(), pos.com_nonking_count());
+
+        // 駒を取り合い、駒が盤面と COM の手駒の間を移動しても一致する。
+        let mut umvs = vec![];
+        for s in [
+            "7g7f", "3c3d", "8h2b+", "3a2b", "B*4e", "2b3c", "4e3c+", "2a3c",
+        ] {
+            umvs.push(pos.do_move(sfen_decode_move(s).unwrap()));
+            assert_eq!(pos.recompute_com_nonking_count(), pos.com_nonking_count());
+        }
+        assert_eq!(pos.com_nonking_count(), 19);
+        assert_eq!(pos.hand(COM)[BISHOP], 2);
+
+        for umv in umvs.into_iter().rev() {
+            pos.undo_move(umv);
+            assert_eq!(pos.recompute_com_nonking_count(), pos.com_nonking_count());
+        }
+        assert_eq!(pos.com_nonking_count(), 19);
+    }
+
+    #[test]
+    fn test_apply_moves() {
+        let orig = sfen_to_position("startpos");
+        let mvs = sfen_decode_moves("7g7f 3c3d 8h2b+ 3a2b B*4e").unwrap();
+
+        let mut pos = orig.clone();
+        let umvs = pos.apply_moves(&mvs).unwrap();
+        assert_eq!(umvs.len(), mvs.len());
+        assert_eq!(pos.ply(), 6);
+        assert_eq!(pos.board()[SQ_45], H_BISHOP);
+
+        pos.undo_moves(&umvs);
+        assert!(pos.same_position(&orig));
+        assert_eq!(pos.ply(), orig.ply());
+
+        // 途中で不正な手(手番でない側の駒を動かす)があれば、局面は元に戻る。
+        let mvs = sfen_decode_moves("7g7f 3c3d 3d3e").unwrap();
+        let mut pos = orig.clone();
+        assert!(pos.apply_moves(&mvs).is_err());
+        assert!(pos.same_position(&orig));
+        assert_eq!(pos.ply(), orig.ply());
+
+        // 持っていない駒は打てない。
+        assert!(pos.apply_moves(&[Move::new_drop(BISHOP, SQ_55)]).is_err());
+        assert!(pos.same_position(&orig));
+    }
+
+    #[test]
+    fn test_side_to_move_in_check() {
+        let mut pos = sfen_to_position("startpos");
+        assert!(!pos.side_to_move_in_check());
+
+        // 王手を含む一連の指し手を指し、各局面で is_checked() と一致することを確認する。
+        let mvs = sfen_decode_moves("7g7f 3c3d 8h2b+ 3a2b B*4e 2b3c 4e3c+ 2a3c S*4b 5a4b").unwrap();
+        let mut check_count = 0;
+        for mv in mvs {
+            pos.do_move(mv);
+            assert_eq!(
+                pos.side_to_move_in_check(),
+                pos.is_checked(pos.side_to_move())
+            );
+            check_count += u32::from(pos.side_to_move_in_check());
+        }
+        assert!(check_count > 0);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn test_null_move() {
+        let mut pos = sfen_to_position(
+            "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+        );
+        let orig = pos.clone();
+
+        pos.do_null_move();
+        assert_eq!(pos.side_to_move(), COM);
+        assert_eq!(pos.ply(), 2);
+        assert_eq!(pos.board(), orig.board());
+
+        pos.undo_null_move();
+        assert_eq!(pos.side_to_move(), HUM);
+        assert_eq!(pos.ply(), 1);
+        assert_eq!(pos.board(), orig.board());
+    }
+
+    #[test]
+    fn test_dead_pieces() {
+        let pos = sfen_to_position("sfen P3k4/9/9/9/9/9/L8/4n4/4K3l b - 1");
+
+        assert_eq!(pos.dead_pieces(HUM), Bitboard::from(SQ_91));
+        assert_eq!(
+            pos.dead_pieces(COM),
+            Bitboard::from(SQ_58) | Bitboard::from(SQ_19)
+        );
+
+        let pos = sfen_to_position(
+            "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+        );
+        assert!(pos.dead_pieces(HUM).is_zero());
+        assert!(pos.dead_pieces(COM).is_zero());
+    }
+
+    #[test]
+    fn test_undoable_matches_board() {
+        let mut pos = sfen_to_position("startpos");
+        let mut umvs = vec![];
+        for mv in sfen_decode_moves("7g7f 3c3d 8h2b+").unwrap() {
+            let umv = UndoableMove::from_move_on_board(mv, pos.board());
+            assert!(pos.undoable_matches_board(umv));
+            umvs.push(pos.do_move(mv));
+        }
+        assert_eq!(umvs[2].piece_captured(), C_BISHOP);
+
+        pos.undo_move(umvs[2]);
+        assert!(pos.undoable_matches_board(umvs[2]));
+
+        // 捕獲した駒を改竄した記録は整合しない。
+        let tampered = UndoableMove::from_move_walk(Move::from(umvs[2]), H_BISHOP, C_SILVER);
+        assert!(!pos.undoable_matches_board(tampered));
+
+        // 手番でない側の指し手は整合しない。
+        assert!(!pos.undoable_matches_board(umvs[1]));
+
+        // 持っていない駒は打てない。
+        let drop = UndoableMove::from_move_drop(Move::new_drop(BISHOP, SQ_55));
+        assert!(!pos.undoable_matches_board(drop));
+    }
+
+    #[test]
+    fn test_content_hash() {
+        // 手順前後で同一局面に至る。
+        let mut pos1 = sfen_to_position("startpos");
+        pos1.apply_moves(&sfen_decode_moves("7g7f 3c3d 2g2f").unwrap())
+            .unwrap();
+        let mut pos2 = sfen_to_position("startpos");
+        pos2.apply_moves(&sfen_decode_moves("2g2f 3c3d 7g7f").unwrap())
+            .unwrap();
+        assert!(pos1.same_position(&pos2));
+        assert_eq!(pos1.content_hash(), pos2.content_hash());
+
+        // 手番が異なれば異なる。
+        let (_, board, hands) = sfen_decode_position(pos1.to_sfen()).unwrap();
+        let pos3 = Position::new(HUM, board, hands);
+        assert_ne!(pos1.content_hash(), pos3.content_hash());
+
+        // 手数は無視する。
+        let pos4 = Position::new(COM, pos1.board().clone(), *pos1.hands());
+        assert_eq!(pos1.content_hash(), pos4.content_hash());
+    }
+
+    #[test]
+    fn test_legal_moves() {
+        // 平手初期局面では両陣営とも 30 手。
+        let mut pos = sfen_to_position("startpos");
+        assert_eq!(pos.legal_moves().len(), 30);
+        let mut pos = Position::new(COM, pos.board().clone(), *pos.hands());
+        assert_eq!(pos.legal_moves().len(), 30);
+
+        // 打ち歩詰めは除かれる。
+        let mut pos = sfen_to_position("sfen 7nk/9/7G1/9/9/9/9/9/4K4 b P 1");
+        let mvs = pos.legal_moves();
+        assert!(!mvs.contains(&Move::new_drop(PAWN, SQ_12)));
+        assert!(mvs.contains(&Move::new_drop(PAWN, SQ_14)));
+
+        // 王手されていれば回避手のみ。
+        let mut pos = sfen_to_position("sfen 4k4/9/9/9/9/9/9/9/r3K4 b - 1");
+        let mvs = pos.legal_moves();
+        assert!(!mvs.is_empty());
+        for mv in mvs {
+            let umv = pos.do_move(mv);
+            assert!(!pos.is_checked(HUM));
+            pos.undo_move(umv);
+        }
+    }
+
+    #[test]
+    fn test_signature() {
+        use std::collections::BTreeMap;
+
+        // 手順前後で同一局面に至る。
+        let mut pos1 = sfen_to_position("startpos");
+        pos1.apply_moves(&sfen_decode_moves("7g7f 3c3d 2g2f 4c4d").unwrap())
+            .unwrap();
+        let mut pos2 = sfen_to_position("startpos");
+        pos2.apply_moves(&sfen_decode_moves("2g2f 4c4d 7g7f 3c3d").unwrap())
+            .unwrap();
+        assert_eq!(pos1.signature(), pos2.signature());
+
+        // 手数は無視する。
+        let pos3 = Position::new(HUM, pos1.board().clone(), *pos1.hands());
+        assert_eq!(pos1.signature(), pos3.signature());
+
+        // 手番や手駒が異なれば異なる。
+        let pos4 = Position::new(COM, pos1.board().clone(), *pos1.hands());
+        assert_ne!(pos1.signature(), pos4.signature());
+        let mut hands = *pos1.hands();
+        hands[HUM][PAWN] = 1;
+        let pos5 = Position::new(HUM, pos1.board().clone(), hands);
+        assert_ne!(pos1.signature(), pos5.signature());
+
+        let mut book = BTreeMap::new();
+        book.insert(pos1.signature(), "book");
+        assert_eq!(book.get(&pos2.signature()), Some(&"book"));
+        assert_eq!(book.get(&pos4.signature()), None);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn test_effect_self_check() {
+        let (side_to_move, board, hands) = Handicap::HumSenteSikenbisha.startpos();
+        let mut pos = Position::new(side_to_move, board, hands);
+
+        Position::set_effect_self_check(true);
+
+        // 正常な局面では何も起こらない。
+        let umv = pos.do_move(sfen_decode_move("7g7f").unwrap());
+        pos.undo_move(umv);
+
+        // 利き数を故意に壊すと、次の do_move() で検出される。
+        pos.effect_counts[HUM][SQ_55] += 1;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            pos.do_move(sfen_decode_move("7g7f").unwrap());
+        }));
+
+        Position::set_effect_self_check(false);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_move_to_string() {
+        let (side_to_move, board, hands) = Handicap::HumSenteSikenbisha.startpos();
+        let mut pos = Position::new(side_to_move, board, hands);
+
+        let mv = sfen_decode_move("7g7f").unwrap();
+        assert_eq!(pos.move_to_string(mv), "▲７六歩");
+        pos.do_move(mv);
+
+        let mv = sfen_decode_move("3c3d").unwrap();
+        assert_eq!(pos.move_to_string(mv), "△３四歩");
+
+        let pos = sfen_to_position("sfen 4k4/9/9/9/9/9/9/1B7/4K4 w Sb 1");
+        assert_eq!(
+            pos.move_to_string(sfen_decode_move("B*5e").unwrap()),
+            "△５五角打"
+        );
+        let pos = sfen_to_position("sfen 4k4/9/9/9/9/9/9/1B7/4K4 b Sb 1");
+        assert_eq!(
+            pos.move_to_string(sfen_decode_move("8h2b+").unwrap()),
+            "▲２二角成"
+        );
+    }
+
+    #[test]
+    fn test_attack_map() {
+        let pos = sfen_to_position(
+            "sfen l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1",
+        );
+
+        for side in Side::iter() {
+            let bb = pos.attack_map(side);
+            for sq in Square::iter() {
+                assert_eq!(bb.test_square(sq), pos.effect_count_board(side)[sq] > 0);
+            }
+        }
+
+        // 玉の周囲には玉自身の利きがある。
+        assert!(pos.attack_map(HUM).test_square(SQ_38));
+        assert!(pos.attack_map(COM).test_square(SQ_13));
+    }
+
+    #[test]
+    fn test_discovered_checkers() {
+        let pos = sfen_to_position("sfen 4k4/9/9/9/8b/9/4P1S2/4R4/L3K4 w - 1");
+
+        // ５七の歩が動けば、５八の飛車が COM 玉に王手をかける。
+        assert_eq!(pos.discovered_checkers(HUM, SQ_57), bbs::square(SQ_58));
+
+        // 間にない駒が動いても開き王手にはならない。
+        assert!(pos.discovered_checkers(HUM, SQ_91).is_zero());
+
+        // ３七の銀が動けば、１五の角が HUM 玉に王手をかける。
+        assert_eq!(pos.discovered_checkers(COM, SQ_37), bbs::square(SQ_15));
+        assert!(pos.discovered_checkers(COM, SQ_57).is_zero());
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "self.hands[us][pc_captured.to_raw_kind()] > 0")]
+    fn test_undo_move_hand_underflow() {
+        let mut pos = sfen_to_position("sfen 4k4/9/9/9/4p4/9/9/4R4/4K4 b - 1");
+
+        let umv = pos.do_move(sfen_decode_move("5h5e").unwrap());
+
+        // 取った駒を手駒から故意に消すと、undo 時に検出される。
+        pos.hands[HUM][PAWN] = 0;
+        pos.undo_move(umv);
+    }
+
+    #[test]
+    fn test_empty_squares() {
+        let pos = sfen_to_position(
+            "sfen l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1",
+        );
+
+        let empty: Vec<_> = pos.empty_squares().collect();
+        assert_eq!(empty.len() as u32, 81 - pos.bb_occupied().count_ones());
+        assert!(empty.iter().all(|&sq| pos.board()[sq] == NO_PIECE));
+        assert!(empty.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_legal_destinations() {
+        // ５七の飛車は５三の香で HUM 玉に釘付けにされている。
+        let mut pos = sfen_to_position("sfen 4k4/9/4l4/9/9/9/4R4/9/4K4 b - 1");
+
+        let expected = bbs::square(SQ_53)
+            | bbs::square(SQ_54)
+            | bbs::square(SQ_55)
+            | bbs::square(SQ_56)
+            | bbs::square(SQ_58);
+        assert_eq!(pos.legal_destinations(SQ_57), expected);
+
+        // 玉は自由に動ける。
+        assert_eq!(pos.legal_destinations(SQ_59).count_ones(), 5);
+
+        // 空白マスや相手の駒のマスは空。
+        assert!(pos.legal_destinations(SQ_55).is_zero());
+        assert!(pos.legal_destinations(SQ_53).is_zero());
+    }
+
+    #[test]
+    fn test_mobility() {
+        // HUM 玉は５三の香に王手されている。
+        let mut pos = sfen_to_position("sfen 4k4/9/4l4/9/9/9/9/9/4K4 b G 1");
+        let pos_before = pos.clone();
+
+        // 疑似合法手には王手を放置する手も含まれる。
+        let n_legal = pos.legal_moves().len() as u32;
+        assert_eq!(n_legal, 9);
+        assert!(pos.mobility(HUM) >= n_legal);
+        assert_eq!(pos.mobility(HUM), 5 + 78);
+
+        // 手番でない側についても数えられ、局面は元に戻っている。
+        assert!(pos.mobility(COM) > 0);
+        assert!(pos.same_position(&pos_before));
+        assert_eq!(pos.side_to_move(), HUM);
+    }
+}
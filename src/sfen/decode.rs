@@ -5,8 +5,9 @@ use crate::shogi::*;
 /// sfen 文字列をデコードし、(手番, 盤面, 両陣営の手駒, 指し手の配列) を返す。
 /// 構文はチェックするが、合法性チェックは一切行わない。
 ///
-/// 文字列の先頭と末尾の空白は無視される。
+/// 文字列の先頭と末尾の空白は無視され、トークン間の空白(タブ含む)の個数は任意。
 /// また、最初のトークンが "position" の場合、それは単に無視される。
+/// よって USI の position コマンド(`position startpos moves ...` など)をそのまま渡せる。
 pub fn sfen_decode(s: impl AsRef<str>) -> anyhow::Result<(Side, Board, Hands, Vec<Move>)> {
     // 先頭と末尾の空白は無視する。
     let s = s.as_ref().trim();
@@ -193,9 +194,10 @@ fn sfen_decode_board_piece(c: char) -> anyhow::Result<(Side, PieceKind)> {
 
 /// sfen 手番文字列をデコードし、手番の陣営を返す。
 fn sfen_decode_side(s: &str) -> anyhow::Result<Side> {
-    match s {
-        "b" => Ok(HUM),
-        "w" => Ok(COM),
+    let mut it = s.chars();
+
+    match (it.next(), it.next()) {
+        (Some(c), None) => Side::from_usi(c).with_context(|| format!("invalid side string: {}", s)),
         _ => bail!("invalid side string: {}", s),
     }
 }
@@ -294,6 +296,17 @@ pub fn sfen_decode_move(s: impl AsRef<str>) -> anyhow::Result<Move> {
     sfen_decode_move_impl(s)
 }
 
+/// 空白区切りの sfen 指し手文字列をデコードし、指し手の配列を返す。
+/// 構文はチェックするが、合法性チェックは一切行わない。
+///
+/// 空文字列(空白のみの場合を含む)は空の配列になる。
+pub fn sfen_decode_moves(s: impl AsRef<str>) -> anyhow::Result<Vec<Move>> {
+    s.as_ref()
+        .split_ascii_whitespace()
+        .map(sfen_decode_move_impl)
+        .collect()
+}
+
 fn sfen_decode_move_impl(s: &str) -> anyhow::Result<Move> {
     sfen_decode_move_walk(s)
         .or_else(|| sfen_decode_move_drop(s))
@@ -20,6 +20,26 @@ where
     s
 }
 
+/// 指し手の配列を空白区切りの sfen 指し手文字列にエンコードする。
+/// 合法性チェックは一切行わない。
+///
+/// 指し手の配列が空の場合、空文字列を返す。
+pub fn sfen_encode_moves<T>(mvs: T) -> String
+where
+    T: AsRef<[Move]>,
+{
+    let mut s = String::new();
+
+    for (i, &mv) in mvs.as_ref().iter().enumerate() {
+        if i > 0 {
+            s.push(' ');
+        }
+        sfen_encode_move_impl(mv, &mut s);
+    }
+
+    s
+}
+
 /// (手番, 盤面, 両陣営の手駒) を sfen 局面文字列にエンコードする。
 /// 合法性チェックは一切行わない。
 ///
@@ -127,11 +147,13 @@ fn sfen_encode_board_piece(pc: Piece, s: &mut String) {
 
 /// 手番の陣営を sfen 手番文字列にエンコードし、既存の文字列に追記する。
 fn sfen_encode_side(side_to_move: Side, s: &mut String) {
-    match side_to_move {
-        HUM => s.push('b'),
-        COM => s.push('w'),
-        _ => panic!("invalid side to move: {:?}", side_to_move),
-    }
+    assert!(
+        side_to_move.is_valid(),
+        "invalid side to move: {:?}",
+        side_to_move
+    );
+
+    s.push(side_to_move.to_usi());
 }
 
 /// 両陣営の手駒を sfen 手駒文字列にエンコードし、既存の文字列に追記する。
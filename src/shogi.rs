@@ -17,6 +17,7 @@ use std::iter::FusedIterator;
 
 use crate::bitop;
 use crate::myarray::*;
+use crate::sfen::sfen_encode_move;
 
 /// 陣営。
 ///
@@ -40,6 +41,30 @@ impl Side {
         Self(self.0 ^ 1)
     }
 
+    /// USI/SFEN の手番文字を陣営に変換する。無効な文字ならば `None` を返す。
+    ///
+    /// 本クレートでは HUM 側を手前(先手側)として扱うので、'b' (先手) が HUM, 'w' (後手) が COM となる。
+    /// 実際の対局で HUM と COM のどちらが先に指すかとは無関係であることに注意。
+    pub const fn from_usi(c: char) -> Option<Self> {
+        match c {
+            'b' => Some(HUM),
+            'w' => Some(COM),
+            _ => None,
+        }
+    }
+
+    /// 陣営を USI/SFEN の手番文字に変換する。HUM は 'b', COM は 'w' となる。
+    /// `self` は有効値でなければならない。
+    pub const fn to_usi(self) -> char {
+        debug_assert!(self.is_valid());
+
+        if self.0 == HUM.0 {
+            'b'
+        } else {
+            'w'
+        }
+    }
+
     /// 陣営を昇順に列挙する。(`HUM`、`COM` の順)
     pub fn iter(
     ) -> impl Iterator<Item = Self> + DoubleEndedIterator + ExactSizeIterator + FusedIterator {
@@ -66,6 +91,15 @@ impl From<Side> for usize {
     }
 }
 
+impl ArrayIndex for Side {
+    fn from_array_index(i: usize) -> Self {
+        let this = Self(i as u32);
+        debug_assert!(this.is_valid());
+
+        this
+    }
+}
+
 impl std::fmt::Debug for Side {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
@@ -183,6 +217,20 @@ impl std::ops::SubAssign<i32> for Col {
     }
 }
 
+/// 内部値から筋を作る。盤面外の値に対してはエラーを返す。
+///
+/// 信頼できない入力に使う。信頼できる値に対しては `Col::from_inner()` を使えばよい。
+impl TryFrom<i32> for Col {
+    type Error = anyhow::Error;
+
+    fn try_from(inner: i32) -> Result<Self, Self::Error> {
+        let col = Self(inner);
+        anyhow::ensure!(col.is_on_board(), "col out of range: {}", inner);
+
+        Ok(col)
+    }
+}
+
 impl From<Col> for i32 {
     fn from(col: Col) -> Self {
         col.0
@@ -205,6 +253,12 @@ impl From<Col> for usize {
     }
 }
 
+impl ArrayIndex for Col {
+    fn from_array_index(i: usize) -> Self {
+        Self::from_inner(i as i32)
+    }
+}
+
 impl std::fmt::Debug for Col {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
@@ -351,6 +405,20 @@ impl std::ops::SubAssign<i32> for Row {
     }
 }
 
+/// 内部値から段を作る。盤面外の値に対してはエラーを返す。
+///
+/// 信頼できない入力に使う。信頼できる値に対しては `Row::from_inner()` を使えばよい。
+impl TryFrom<i32> for Row {
+    type Error = anyhow::Error;
+
+    fn try_from(inner: i32) -> Result<Self, Self::Error> {
+        let row = Self(inner);
+        anyhow::ensure!(row.is_on_board(), "row out of range: {}", inner);
+
+        Ok(row)
+    }
+}
+
 impl From<Row> for i32 {
     fn from(row: Row) -> Self {
         row.0
@@ -373,6 +441,12 @@ impl From<Row> for usize {
     }
 }
 
+impl ArrayIndex for Row {
+    fn from_array_index(i: usize) -> Self {
+        Self::from_inner(i as i32)
+    }
+}
+
 impl std::fmt::Debug for Row {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
@@ -567,6 +641,20 @@ impl Square {
         TABLE[self.0 as usize]
     }
 
+    /// 筋を左右反転したマスを返す。(１一 <-> ９一 など)
+    pub const fn mirror_horizontal(self) -> Self {
+        debug_assert!(self.is_on_board());
+
+        Self::from_col_row(Col(COL_9.0 - self.col().0), self.row())
+    }
+
+    /// 盤面を 180 度回転したときのマスを返す。(１一 <-> ９九 など)
+    pub const fn rotate180(self) -> Self {
+        debug_assert!(self.is_on_board());
+
+        Self(SQ_99.0 - self.0)
+    }
+
     /// 2 つのマスの間のチェス盤距離を返す。`self`, `other` は盤面上のマスでなければならない。
     pub const fn distance(self, other: Self) -> u8 {
         debug_assert!(self.is_on_board());
@@ -600,6 +688,17 @@ impl Square {
         TABLE[self.0 as usize][other.0 as usize]
     }
 
+    /// 2 つのマスの間のマンハッタン距離を返す。`self`, `other` は盤面上のマスでなければならない。
+    pub const fn manhattan_distance(self, other: Self) -> u8 {
+        debug_assert!(self.is_on_board());
+        debug_assert!(other.is_on_board());
+
+        let dx_abs = (self.col().0 - other.col().0).unsigned_abs() as u8;
+        let dy_abs = (self.row().0 - other.row().0).unsigned_abs() as u8;
+
+        dx_abs + dy_abs
+    }
+
     /// マスが指定した陣営にとって敵陣かどうかを返す。
     pub const fn is_promotion_zone(self, side: Side) -> bool {
         self.row().is_promotion_zone(side)
@@ -683,6 +782,20 @@ impl std::ops::SubAssign<i32> for Square {
     }
 }
 
+/// 内部値からマスを作る。盤面外の値に対してはエラーを返す。
+///
+/// 信頼できない入力に使う。信頼できる値に対しては `Square::from_inner()` を使えばよい。
+impl TryFrom<i32> for Square {
+    type Error = anyhow::Error;
+
+    fn try_from(inner: i32) -> Result<Self, Self::Error> {
+        let sq = Self(inner);
+        anyhow::ensure!(sq.is_on_board(), "square out of range: {}", inner);
+
+        Ok(sq)
+    }
+}
+
 impl From<Square> for i32 {
     fn from(sq: Square) -> Self {
         sq.0
@@ -705,6 +818,12 @@ impl From<Square> for usize {
     }
 }
 
+impl ArrayIndex for Square {
+    fn from_array_index(i: usize) -> Self {
+        Self::from_inner(i as i32)
+    }
+}
+
 impl std::fmt::Debug for Square {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
@@ -1171,6 +1290,19 @@ impl PieceKind {
         Self(self.0 & 7)
     }
 
+    /// 成っていない駒種を返す。`to_raw()` と異なり任意の有効値を渡せる。
+    ///
+    /// 玉と `NO_PIECE_KIND` に対しては `None` を返す。
+    pub const fn unpromoted(self) -> Option<Self> {
+        debug_assert!(self.is_valid());
+
+        if self.0 == KING.0 || self.0 == NO_PIECE_KIND.0 {
+            None
+        } else {
+            Some(Self(self.0 & 7))
+        }
+    }
+
     /// 実際の駒である駒種を昇順に列挙する。
     pub fn iter_piece(
     ) -> impl Iterator<Item = Self> + DoubleEndedIterator + ExactSizeIterator + FusedIterator {
@@ -1182,6 +1314,17 @@ impl PieceKind {
         .into_iter()
     }
 
+    /// 成駒でない実際の駒種(玉を含む)を昇順に列挙する。
+    pub fn iter_raw() -> impl DoubleEndedIterator<Item = Self> + ExactSizeIterator + FusedIterator {
+        [PAWN, LANCE, KNIGHT, SILVER, BISHOP, ROOK, GOLD, KING].into_iter()
+    }
+
+    /// 成駒である駒種を昇順に列挙する。
+    pub fn iter_promoted(
+    ) -> impl DoubleEndedIterator<Item = Self> + ExactSizeIterator + FusedIterator {
+        [PRO_PAWN, PRO_LANCE, PRO_KNIGHT, PRO_SILVER, HORSE, DRAGON].into_iter()
+    }
+
     /// 手駒となりうる駒種を昇順に列挙する。
     pub fn iter_hand(
     ) -> impl Iterator<Item = Self> + DoubleEndedIterator + ExactSizeIterator + FusedIterator {
@@ -1208,6 +1351,15 @@ impl From<PieceKind> for usize {
     }
 }
 
+impl ArrayIndex for PieceKind {
+    fn from_array_index(i: usize) -> Self {
+        let this = Self(i as u32);
+        debug_assert!(this.is_valid());
+
+        this
+    }
+}
+
 impl std::fmt::Debug for PieceKind {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
@@ -1294,6 +1446,8 @@ pub const C_DRAGON: Piece = Piece(30);
 
 impl Piece {
     /// 陣営と駒種を指定して駒を作る。pk は実際の駒でなければならない。
+    ///
+    /// 成駒の駒種も受け付ける。例えば `Piece::new(COM, DRAGON) == C_DRAGON` となる。
     pub const fn new(side: Side, pk: PieceKind) -> Self {
         debug_assert!(pk.is_piece());
 
@@ -1339,6 +1493,22 @@ impl Piece {
         PieceKind(self.0 & 0xF)
     }
 
+    /// 駒種(成/不成も含む)はそのままで陣営を入れ替えた駒を返す。`NO_PIECE` はそのまま返す。
+    pub const fn flip_side(self) -> Self {
+        if self.0 == NO_PIECE.0 {
+            return self;
+        }
+
+        Self(self.0 ^ (1 << 4))
+    }
+
+    /// 駒種(成/不成も含む)はそのままで陣営を `side` にした駒を返す。`self` は実際の駒でなければならない。
+    pub const fn with_side(self, side: Side) -> Self {
+        debug_assert!(self.is_piece());
+
+        Self((side.0 << 4) | (self.0 & 0xF))
+    }
+
     /// 成った駒を返す。`self` は成れる駒でなければならない。
     pub const fn to_promoted(self) -> Self {
         debug_assert!(self.is_promotable());
@@ -1453,6 +1623,29 @@ impl Move {
         Self((dst.0 as u32) | (pk.0 << 7) | Self::FLAG_DROP)
     }
 
+    /// 内部値を指定して指し手を作る。無効な指し手を表す値を渡してはならない。
+    ///
+    /// null move は有効な指し手ではないので、これで作ることはできない(`Move::null()` を使うこと)。
+    pub const fn from_inner(inner: u32) -> Self {
+        let this = Self(inner);
+        debug_assert!(this.is_valid());
+
+        this
+    }
+
+    /// 「指し手なし」を表す null move を返す。
+    ///
+    /// 移動元と移動先が同じなので、null move は決して有効な指し手ではない。局面に適用してはならない。
+    /// `Option<Move>` を使いたくない箇所でのプレースホルダとして使う。
+    pub const fn null() -> Self {
+        Self(0)
+    }
+
+    /// null move かどうかを返す。
+    pub const fn is_null(self) -> bool {
+        self.0 == 0
+    }
+
     /// 指し手が有効かどうかを返す。盤面は考慮しない。
     ///
     /// 有効な指し手の定義は以下の通り:
@@ -1504,6 +1697,33 @@ impl Move {
 
         PieceKind((self.0 >> 7) & 0x7F)
     }
+
+    /// USI 形式の指し手文字列 ("7g7f", "P*5e" など) を返す。`sfen_encode_move()` を参照。
+    pub fn to_usi(self) -> String {
+        sfen_encode_move(self)
+    }
+
+    /// 盤面を 180 度回転したときの指し手を返す。`Position::flipped()` に対応する指し手を得るのに使う。
+    pub const fn rotate180(self) -> Self {
+        if self.is_drop() {
+            Self::new_drop(self.dropped_piece_kind(), self.dst().rotate180())
+        } else if self.is_promotion() {
+            Self::new_walk_promotion(self.src().rotate180(), self.dst().rotate180())
+        } else {
+            Self::new_walk(self.src().rotate180(), self.dst().rotate180())
+        }
+    }
+
+    /// 内部値を返す。`const` 文脈で使える。
+    pub const fn inner(self) -> u32 {
+        self.0
+    }
+}
+
+impl From<Move> for u32 {
+    fn from(mv: Move) -> Self {
+        mv.0
+    }
 }
 
 impl std::fmt::Debug for Move {
@@ -1593,6 +1813,26 @@ impl UndoableMove {
         Self(mv.0)
     }
 
+    /// 内部値を指定して `UndoableMove` を作る。無効な指し手を表す値を渡してはならない。
+    pub const fn from_inner(inner: u32) -> Self {
+        let this = Self(inner);
+        debug_assert!(this.is_valid());
+
+        this
+    }
+
+    /// 指し手と着手前の盤面から `UndoableMove` を作る。盤面は変更しない。
+    ///
+    /// 盤上の駒を動かす指し手の場合、移動元の駒と捕獲する駒を盤面から読み取る。
+    /// 合法性チェックは一切行わない。
+    pub fn from_move_on_board(mv: Move, board: &Board) -> Self {
+        if mv.is_drop() {
+            Self::from_move_drop(mv)
+        } else {
+            Self::from_move_walk(mv, board[mv.src()], board[mv.dst()])
+        }
+    }
+
     /// 指し手が有効かどうかを返す。盤面は考慮しない。
     ///
     /// 有効な指し手の定義は以下の通り:
@@ -1672,6 +1912,18 @@ impl UndoableMove {
         Piece((self.0 >> 21) & 0x1F)
     }
 
+    /// USI 形式の指し手文字列を返す。`Move::to_usi()` を参照。
+    pub fn to_usi(self) -> String {
+        Move::from(self).to_usi()
+    }
+
+    /// 可逆な(駒打ちでも駒取りでも成りでもない)指し手かどうかを返す。
+    ///
+    /// 不可逆な手の前後で同一局面は現れないので、千日手判定などで遡る範囲を限定するのに使える。
+    pub const fn is_reversible(self) -> bool {
+        !self.is_drop() && !self.is_promotion() && self.piece_captured().0 == NO_PIECE.0
+    }
+
     /// 移動後の駒を返す。`self` は盤上の駒を動かす指し手でなければならない。
     ///
     /// 移動元の駒と成りフラグから計算される。
@@ -1692,6 +1944,17 @@ impl UndoableMove {
 
         PieceKind((self.0 >> 7) & 0x7F)
     }
+
+    /// 内部値を返す。`const` 文脈で使える。
+    pub const fn inner(self) -> u32 {
+        self.0
+    }
+}
+
+impl From<UndoableMove> for u32 {
+    fn from(umv: UndoableMove) -> Self {
+        umv.0
+    }
 }
 
 impl From<UndoableMove> for Move {
@@ -1792,6 +2055,59 @@ impl Board {
 
         Self(INNER)
     }
+
+    /// 空の盤面に `(マス, 駒)` の組を順に配置した盤面を返す。
+    ///
+    /// 同じマスに 2 回以上駒を置いてはならない。
+    pub fn from_pieces<I>(pieces: I) -> Self
+    where
+        I: IntoIterator<Item = (Square, Piece)>,
+    {
+        let mut board = Self::empty();
+
+        for (sq, pc) in pieces {
+            debug_assert_eq!(board[sq], NO_PIECE, "square {:?} is set twice", sq);
+            board[sq] = pc;
+        }
+
+        board
+    }
+
+    /// 盤面を 180 度回転し、全ての駒の陣営を入れ替えた盤面を返す。
+    pub fn flip_sides(&self) -> Self {
+        let mut flipped = Self::empty();
+
+        for sq in Square::iter() {
+            let pc = self[sq];
+            if pc == NO_PIECE {
+                continue;
+            }
+            flipped[sq.rotate180()] = pc.flip_side();
+        }
+
+        flipped
+    }
+
+    /// 盤上にある指定した陣営、駒種の駒の数を返す。`pk` は実際の駒でなければならない。
+    pub fn count_kind(&self, side: Side, pk: PieceKind) -> u32 {
+        debug_assert!(pk.is_piece());
+
+        let pc = Piece::new(side, pk);
+        self.0.iter().filter(|&&x| x == pc).count() as u32
+    }
+
+    /// 盤上の駒の数を陣営ごと、駒種ごとに数えて返す。`census[side][pk]` のようにアクセスする。
+    ///
+    /// 駒種 `NO_PIECE_KIND` に対応する要素は常に 0。
+    pub fn census(&self) -> [[u32; 15]; 2] {
+        let mut census = [[0; 15]; 2];
+
+        for &pc in self.0.iter().filter(|pc| pc.is_piece()) {
+            census[usize::from(pc.side())][usize::from(pc.kind())] += 1;
+        }
+
+        census
+    }
 }
 
 impl std::ops::Index<Square> for Board {
@@ -1853,6 +2169,56 @@ impl Hand {
 
         true
     }
+
+    /// 駒種 `pk` の個数を 1 減らす。個数が 0 の場合は何もせず `None` を返す。
+    ///
+    /// `hand[pk] -= 1` はリリースビルドでは個数 0 のとき黙ってラップアラウンドしてしまうので、
+    /// 手駒の不整合を検出したい箇所ではこちらを使う。
+    pub fn try_decrement(&mut self, pk: PieceKind) -> Option<()> {
+        let n = &mut self[pk];
+        if *n == 0 {
+            return None;
+        }
+
+        *n -= 1;
+
+        Some(())
+    }
+
+    /// 個数が 0 でない駒種とその個数を駒種の昇順に列挙する。
+    pub fn iter(&self) -> impl Iterator<Item = (PieceKind, u32)> + '_ {
+        PieceKind::iter_hand().filter_map(|pk| {
+            let n = self[pk];
+            (n > 0).then_some((pk, n))
+        })
+    }
+
+    /// KIF 形式の持駒表記を返す。(例: "飛　角　歩三")
+    ///
+    /// 駒種は飛, 角, 金, 銀, 桂, 香, 歩の順で、全角空白で区切る。個数は 2 以上のとき漢数字で付ける。
+    /// 手駒が空の場合は "なし" を返す。
+    pub fn to_kif(&self) -> String {
+        const PKS: [PieceKind; 7] = [ROOK, BISHOP, GOLD, SILVER, KNIGHT, LANCE, PAWN];
+        const DIGITS: [&str; 10] = ["", "一", "二", "三", "四", "五", "六", "七", "八", "九"];
+
+        if self.is_empty() {
+            return "なし".to_owned();
+        }
+
+        let strs: Vec<_> = PKS
+            .into_iter()
+            .filter(|&pk| self[pk] > 0)
+            .map(|pk| {
+                let n = self[pk] as usize;
+                assert!(n <= 18);
+                let tens = if n >= 10 { "十" } else { "" };
+                let count = if n >= 2 { DIGITS[n % 10] } else { "" };
+                format!("{}{}{}", pk, tens, count)
+            })
+            .collect();
+
+        strs.join("　")
+    }
 }
 
 impl std::ops::Index<PieceKind> for Hand {
@@ -2141,6 +2507,16 @@ impl DirectionSet {
         self.0 == 0
     }
 
+    /// `self` が含む方向の数を返す。
+    pub const fn count(self) -> u32 {
+        self.0.count_ones()
+    }
+
+    /// `self` がちょうど 1 つの方向のみを含むかどうかを返す。
+    pub const fn is_single(self) -> bool {
+        self.0.is_power_of_two()
+    }
+
     /// `self` と `other` の共通部分が空かどうかを返す。
     pub const fn is_disjoint(self, other: Self) -> bool {
         self.and(other).is_empty()
@@ -2324,6 +2700,147 @@ mod tests {
     #[allow(unused_imports)]
     use pretty_assertions::{assert_eq, assert_ne};
 
+    #[test]
+    fn test_undoable_move_from_move_on_board() {
+        let mut board = Board::startpos();
+        board[SQ_77] = NO_PIECE;
+        board[SQ_76] = H_PAWN;
+        board[SQ_33] = NO_PIECE;
+        board[SQ_34] = C_PAWN;
+
+        // 駒取りかつ成り。
+        let mv = Move::new_walk_promotion(SQ_88, SQ_22);
+        let umv = UndoableMove::from_move_on_board(mv, &board);
+        assert_eq!(umv, UndoableMove::from_move_walk(mv, H_BISHOP, C_BISHOP));
+        assert_eq!(umv.piece_src(), H_BISHOP);
+        assert_eq!(umv.piece_captured(), C_BISHOP);
+        assert_eq!(umv.piece_dst(), H_HORSE);
+        assert_eq!(Move::from(umv), mv);
+
+        // 駒取りでない。
+        let mv = Move::new_walk(SQ_39, SQ_38);
+        let umv = UndoableMove::from_move_on_board(mv, &board);
+        assert_eq!(umv.piece_src(), H_SILVER);
+        assert_eq!(umv.piece_captured(), NO_PIECE);
+
+        // 駒打ち。
+        let mv = Move::new_drop(BISHOP, SQ_55);
+        let umv = UndoableMove::from_move_on_board(mv, &board);
+        assert_eq!(umv, UndoableMove::from_move_drop(mv));
+        assert!(umv.is_drop());
+        assert_eq!(umv.dropped_piece_kind(), BISHOP);
+    }
+
+    #[test]
+    fn test_move_null() {
+        assert!(Move::null().is_null());
+        assert!(!Move::null().is_valid());
+
+        assert!(!Move::new_walk(SQ_77, SQ_76).is_null());
+        assert!(!Move::new_walk(SQ_12, SQ_11).is_null());
+        assert!(!Move::new_walk_promotion(SQ_13, SQ_12).is_null());
+        assert!(!Move::new_drop(PAWN, SQ_11).is_null());
+    }
+
+    #[test]
+    fn test_board_from_pieces() {
+        let startpos = Board::startpos();
+
+        let board = Board::from_pieces(
+            Square::iter()
+                .map(|sq| (sq, startpos[sq]))
+                .filter(|&(_, pc)| pc != NO_PIECE),
+        );
+        assert_eq!(board, startpos);
+
+        let board = Board::from_pieces([(SQ_51, C_KING), (SQ_59, H_KING)]);
+        let mut expected = Board::empty();
+        expected[SQ_51] = C_KING;
+        expected[SQ_59] = H_KING;
+        assert_eq!(board, expected);
+
+        assert_eq!(Board::from_pieces([]), Board::empty());
+    }
+
+    #[test]
+    fn test_board_census() {
+        let startpos = Board::startpos();
+
+        let census = startpos.census();
+        for side in Side::iter() {
+            let expected = [
+                (PAWN, 9),
+                (LANCE, 2),
+                (KNIGHT, 2),
+                (SILVER, 2),
+                (BISHOP, 1),
+                (ROOK, 1),
+                (GOLD, 2),
+                (KING, 1),
+            ];
+            for (pk, n) in expected {
+                assert_eq!(startpos.count_kind(side, pk), n);
+                assert_eq!(census[usize::from(side)][usize::from(pk)], n);
+            }
+            assert_eq!(census[usize::from(side)].iter().sum::<u32>(), 20);
+        }
+
+        let mut board = Board::empty();
+        board[SQ_55] = C_DRAGON;
+        assert_eq!(board.count_kind(COM, DRAGON), 1);
+        assert_eq!(board.count_kind(COM, ROOK), 0);
+        assert_eq!(board.count_kind(HUM, DRAGON), 0);
+    }
+
+    #[test]
+    fn test_piece_kind_iter_raw_promoted() {
+        assert_eq!(PieceKind::iter_raw().len(), 8);
+        assert_eq!(PieceKind::iter_promoted().len(), 6);
+
+        assert!(PieceKind::iter_raw().all(|pk| pk.is_piece() && !pk.is_promoted()));
+        assert!(PieceKind::iter_promoted().all(|pk| pk.is_piece() && pk.is_promoted()));
+
+        // 両者を合わせると iter_piece() に一致する。
+        let all: Vec<_> = PieceKind::iter_raw()
+            .chain(PieceKind::iter_promoted())
+            .collect();
+        assert_eq!(all, PieceKind::iter_piece().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_piece_kind_unpromoted() {
+        assert_eq!(NO_PIECE_KIND.unpromoted(), None);
+        assert_eq!(PAWN.unpromoted(), Some(PAWN));
+        assert_eq!(LANCE.unpromoted(), Some(LANCE));
+        assert_eq!(KNIGHT.unpromoted(), Some(KNIGHT));
+        assert_eq!(SILVER.unpromoted(), Some(SILVER));
+        assert_eq!(BISHOP.unpromoted(), Some(BISHOP));
+        assert_eq!(ROOK.unpromoted(), Some(ROOK));
+        assert_eq!(GOLD.unpromoted(), Some(GOLD));
+        assert_eq!(KING.unpromoted(), None);
+        assert_eq!(PRO_PAWN.unpromoted(), Some(PAWN));
+        assert_eq!(PRO_LANCE.unpromoted(), Some(LANCE));
+        assert_eq!(PRO_KNIGHT.unpromoted(), Some(KNIGHT));
+        assert_eq!(PRO_SILVER.unpromoted(), Some(SILVER));
+        assert_eq!(HORSE.unpromoted(), Some(BISHOP));
+        assert_eq!(DRAGON.unpromoted(), Some(ROOK));
+    }
+
+    #[test]
+    fn test_side_usi() {
+        assert_eq!(HUM.to_usi(), 'b');
+        assert_eq!(COM.to_usi(), 'w');
+
+        assert_eq!(Side::from_usi('b'), Some(HUM));
+        assert_eq!(Side::from_usi('w'), Some(COM));
+        assert_eq!(Side::from_usi('B'), None);
+        assert_eq!(Side::from_usi('x'), None);
+
+        for side in Side::iter() {
+            assert_eq!(Side::from_usi(side.to_usi()), Some(side));
+        }
+    }
+
     #[test]
     fn test_row_is_promotion_zone() {
         assert!(ROW_1.is_promotion_zone(HUM));
@@ -2357,6 +2874,24 @@ mod tests {
         assert_eq!(SQ_91.distance(SQ_19), 8);
     }
 
+    #[test]
+    fn test_square_manhattan_distance() {
+        assert_eq!(SQ_11.manhattan_distance(SQ_11), 0);
+        assert_eq!(SQ_11.manhattan_distance(SQ_99), 16);
+        assert_eq!(SQ_23.manhattan_distance(SQ_33), 1);
+        assert_eq!(SQ_23.manhattan_distance(SQ_34), 2);
+        assert_eq!(SQ_75.manhattan_distance(SQ_83), 3);
+        assert_eq!(SQ_75.manhattan_distance(SQ_34), 5);
+        assert_eq!(SQ_91.manhattan_distance(SQ_19), 16);
+
+        for sq1 in Square::iter() {
+            for sq2 in Square::iter() {
+                assert_eq!(sq1.manhattan_distance(sq2), sq2.manhattan_distance(sq1));
+                assert!(sq1.manhattan_distance(sq2) >= sq1.distance(sq2));
+            }
+        }
+    }
+
     #[test]
     fn test_direction_set_basic() {
         assert!(DirectionSet::empty().is_empty());
@@ -2386,6 +2921,21 @@ mod tests {
         assert!(!DIRS.is_superset(DirectionSet::RU | DirectionSet::U));
     }
 
+    #[test]
+    fn test_direction_set_count() {
+        assert_eq!(DirectionSet::empty().count(), 0);
+        assert!(!DirectionSet::empty().is_single());
+
+        assert_eq!(DirectionSet::LU.count(), 1);
+        assert!(DirectionSet::LU.is_single());
+
+        assert_eq!((DirectionSet::U | DirectionSet::D).count(), 2);
+        assert!(!(DirectionSet::U | DirectionSet::D).is_single());
+
+        assert_eq!(DirectionSet::all().count(), 8);
+        assert!(!DirectionSet::all().is_single());
+    }
+
     #[test]
     fn test_direction_set_bitop() {
         const DIRS: DirectionSet = DirectionSet::RU.or(DirectionSet::D).or(DirectionSet::L);
@@ -2467,4 +3017,168 @@ mod tests {
 
         assert_eq!(dirs, DirectionSet::empty());
     }
+
+    #[test]
+    fn test_piece_flip_side() {
+        assert_eq!(H_PAWN.flip_side(), C_PAWN);
+        assert_eq!(C_KING.flip_side(), H_KING);
+        assert_eq!(H_DRAGON.flip_side(), C_DRAGON);
+        assert_eq!(C_PRO_SILVER.flip_side(), H_PRO_SILVER);
+        assert_eq!(NO_PIECE.flip_side(), NO_PIECE);
+
+        assert_eq!(H_HORSE.with_side(COM), C_HORSE);
+        assert_eq!(H_HORSE.with_side(HUM), H_HORSE);
+        assert_eq!(C_PRO_PAWN.with_side(HUM), H_PRO_PAWN);
+
+        for pc in Side::iter()
+            .flat_map(|side| PieceKind::iter_piece().map(move |pk| Piece::new(side, pk)))
+        {
+            assert_eq!(pc.flip_side().flip_side(), pc);
+            assert_eq!(pc.flip_side().kind(), pc.kind());
+            assert_eq!(pc.flip_side().side(), pc.side().inv());
+            assert_eq!(pc.with_side(pc.side()), pc);
+        }
+    }
+
+    #[test]
+    fn test_piece_new() {
+        assert_eq!(Piece::new(COM, DRAGON), C_DRAGON);
+        assert_eq!(Piece::new(HUM, PRO_PAWN), H_PRO_PAWN);
+        assert_eq!(Piece::new(HUM, KING), H_KING);
+
+        for side in Side::iter() {
+            for pk in PieceKind::iter_piece() {
+                let pc = Piece::new(side, pk);
+                assert!(pc.is_piece());
+                assert_eq!(pc.side(), side);
+                assert_eq!(pc.kind(), pk);
+            }
+        }
+    }
+
+    #[test]
+    fn test_square_mirror_rotate() {
+        assert_eq!(SQ_11.mirror_horizontal(), SQ_91);
+        assert_eq!(SQ_76.mirror_horizontal(), SQ_36);
+        assert_eq!(SQ_55.mirror_horizontal(), SQ_55);
+
+        assert_eq!(SQ_11.rotate180(), SQ_99);
+        assert_eq!(SQ_76.rotate180(), SQ_34);
+        assert_eq!(SQ_55.rotate180(), SQ_55);
+
+        for sq in Square::iter() {
+            assert_eq!(sq.mirror_horizontal().mirror_horizontal(), sq);
+            assert_eq!(sq.rotate180().rotate180(), sq);
+            assert_eq!(sq.mirror_horizontal().row(), sq.row());
+        }
+
+        // const 文脈で使える。
+        const SQ: Square = SQ_19.rotate180();
+        assert_eq!(SQ, SQ_91);
+    }
+
+    #[test]
+    fn test_move_inner_roundtrip() {
+        let mvs = [
+            Move::new_walk(SQ_77, SQ_76),
+            Move::new_walk_promotion(SQ_88, SQ_22),
+            Move::new_drop(PAWN, SQ_55),
+            Move::new_drop(ROOK, SQ_11),
+        ];
+        for mv in mvs {
+            assert_eq!(Move::from_inner(mv.inner()), mv);
+            assert_eq!(u32::from(mv), mv.inner());
+        }
+
+        let umvs = [
+            UndoableMove::from_move_walk(mvs[0], H_PAWN, NO_PIECE),
+            UndoableMove::from_move_walk(mvs[1], H_BISHOP, C_BISHOP),
+            UndoableMove::from_move_drop(mvs[2]),
+            UndoableMove::from_move_drop(mvs[3]),
+        ];
+        for umv in umvs {
+            assert_eq!(UndoableMove::from_inner(umv.inner()), umv);
+            assert_eq!(u32::from(umv), umv.inner());
+            assert_eq!(Move::from_inner(Move::from(umv).inner()), Move::from(umv));
+        }
+    }
+
+    #[test]
+    fn test_hand_to_kif() {
+        assert_eq!(Hand::empty().to_kif(), "なし");
+
+        let mut hand = Hand::empty();
+        hand[GOLD] = 1;
+        assert_eq!(hand.to_kif(), "金");
+
+        hand[PAWN] = 18;
+        assert_eq!(hand.to_kif(), "金　歩十八");
+
+        hand[PAWN] = 10;
+        hand[ROOK] = 2;
+        hand[LANCE] = 3;
+        assert_eq!(hand.to_kif(), "飛二　金　香三　歩十");
+    }
+
+    #[test]
+    fn test_try_from_i32() {
+        assert_eq!(Col::try_from(0).unwrap(), COL_1);
+        assert_eq!(Col::try_from(8).unwrap(), COL_9);
+        assert!(Col::try_from(9).is_err());
+        assert!(Col::try_from(-1).is_err());
+
+        assert_eq!(Row::try_from(0).unwrap(), ROW_1);
+        assert!(Row::try_from(9).is_err());
+
+        assert_eq!(Square::try_from(0).unwrap(), SQ_11);
+        assert_eq!(Square::try_from(80).unwrap(), SQ_99);
+        assert!(Square::try_from(81).is_err());
+        assert!(Square::try_from(-1).is_err());
+    }
+
+    #[test]
+    fn test_undoable_move_is_reversible() {
+        let walk = Move::new_walk(SQ_77, SQ_76);
+        assert!(UndoableMove::from_move_walk(walk, H_PAWN, NO_PIECE).is_reversible());
+
+        let capture = Move::new_walk(SQ_88, SQ_22);
+        assert!(!UndoableMove::from_move_walk(capture, H_BISHOP, C_BISHOP).is_reversible());
+
+        let promotion = Move::new_walk_promotion(SQ_88, SQ_22);
+        assert!(!UndoableMove::from_move_walk(promotion, H_BISHOP, NO_PIECE).is_reversible());
+
+        let drop = Move::new_drop(PAWN, SQ_55);
+        assert!(!UndoableMove::from_move_drop(drop).is_reversible());
+    }
+
+    #[test]
+    fn test_undoable_move_to_usi() {
+        let capture = UndoableMove::from_move_walk(
+            Move::new_walk_promotion(SQ_88, SQ_22),
+            H_BISHOP,
+            C_BISHOP,
+        );
+        assert_eq!(capture.to_usi(), "8h2b+");
+        assert_eq!(capture.to_usi(), Move::from(capture).to_usi());
+
+        let drop = UndoableMove::from_move_drop(Move::new_drop(PAWN, SQ_55));
+        assert_eq!(drop.to_usi(), "P*5e");
+        assert_eq!(drop.to_usi(), Move::from(drop).to_usi());
+    }
+
+    #[test]
+    fn test_hand_try_decrement() {
+        let mut hand = Hand::empty();
+        hand[PAWN] = 2;
+
+        assert_eq!(hand.try_decrement(PAWN), Some(()));
+        assert_eq!(hand.try_decrement(PAWN), Some(()));
+        assert_eq!(hand[PAWN], 0);
+
+        // 個数 0 の駒種は減らせず、個数も変わらない。
+        assert_eq!(hand.try_decrement(PAWN), None);
+        assert_eq!(hand[PAWN], 0);
+        assert_eq!(hand.try_decrement(ROOK), None);
+        assert!(hand.is_empty());
+    }
 }
@@ -0,0 +1,30 @@
+#[allow(unused_imports)]
+use pretty_assertions::{assert_eq, assert_ne};
+
+use naitou_clone::*;
+
+/// 打ち歩詰めは原作通りの判定では詰みだが、通常の将棋のルールでは詰みではないことをテストする。
+#[test]
+fn test_checkmated_drop_pawn_mate() {
+    bbs::init();
+
+    // COM が１八に歩を打つと HUM 玉は逃げられない。
+    let mut pos = sfen_to_position("sfen 4k4/9/9/9/9/9/7g1/9/7NK w p 1");
+    let umv = pos.do_move(Move::new_drop(PAWN, SQ_18));
+    assert!(pos.is_checked(HUM));
+
+    assert!(position_is_checkmated(&mut pos));
+    assert!(position_is_checkmated_naitou(&mut pos));
+    assert!(!is_checkmated_standard(&mut pos, Some(umv)));
+
+    // 直前の指し手が歩打ちでなければ通常の詰み。
+    let mut pos = sfen_to_position("sfen 4k4/9/9/9/9/9/7g1/8p/7NK b - 1");
+    assert!(position_is_checkmated(&mut pos));
+    assert!(is_checkmated_standard(&mut pos, None));
+}
+
+fn sfen_to_position(sfen: &str) -> Position {
+    let (side_to_move, board, hands) = sfen_decode_position(sfen).unwrap();
+
+    Position::new(side_to_move, board, hands)
+}
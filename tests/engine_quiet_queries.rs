@@ -0,0 +1,57 @@
+//! 思考エンジンに対する読み取り専用の問い合わせが思考ログを出力しないことを確かめる。
+
+#![cfg(feature = "engine-trace")]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use naitou_clone::*;
+
+/// 出力されたログレコード数を数えるだけのロガー。
+struct CountingLogger;
+
+static RECORD_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+impl log::Log for CountingLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, _record: &log::Record) {
+        RECORD_COUNT.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CountingLogger = CountingLogger;
+
+fn mv(s: &str) -> Move {
+    sfen_decode_move(s).unwrap()
+}
+
+#[test]
+fn test_engine_quiet_queries() {
+    bbs::init();
+
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let (mut engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+
+    let count_before = RECORD_COUNT.load(Ordering::SeqCst);
+
+    assert!(!engine.rank_hum_moves().is_empty());
+    engine.would_suppress_check(mv("7g7f"), mv("3c3d")).unwrap();
+    engine
+        .is_rejected_as_sacrifice(mv("7g7f"), mv("4c4d"))
+        .unwrap();
+    engine
+        .debug_compare(mv("7g7f"), mv("3c3d"), mv("4c4d"))
+        .unwrap();
+
+    assert_eq!(RECORD_COUNT.load(Ordering::SeqCst), count_before);
+
+    // 通常の思考ではログが出力される。
+    engine.do_step(mv("7g7f")).unwrap();
+    assert!(RECORD_COUNT.load(Ordering::SeqCst) > count_before);
+}
@@ -0,0 +1,58 @@
+//! `engine-trace` feature が無効な場合、思考ログ出力が全て消えることを確かめる。
+//!
+//! `cargo test --no-default-features --test engine_trace_disabled` で実行する。
+
+#![cfg(not(feature = "engine-trace"))]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use naitou_clone::*;
+
+/// 出力されたログレコード数を数えるだけのロガー。
+struct CountingLogger;
+
+static RECORD_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+impl log::Log for CountingLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, _record: &log::Record) {
+        RECORD_COUNT.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CountingLogger = CountingLogger;
+
+#[test]
+fn test_engine_trace_disabled() {
+    bbs::init();
+
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    // ロガー自体は機能している。
+    log::info!("sanity check");
+    assert_eq!(RECORD_COUNT.load(Ordering::SeqCst), 1);
+
+    // 定跡処理と探索の両方を通しても、ログレコードは 1 つも出力されない。
+    let (mut engine, _) = Engine::new(Handicap::HumSenteSikenbisha);
+    let mut searched = false;
+    for _ in 0..10 {
+        let mv = engine.position().clone().legal_moves()[0];
+        engine.do_step(mv).unwrap();
+        searched |= engine.last_think_stats().generated_count > 0;
+        if engine.is_terminal() {
+            break;
+        }
+    }
+    assert!(searched);
+
+    mylog::log_think_start(1);
+    mylog::log_cand_start(Move::new_drop(PAWN, SQ_55));
+
+    assert_eq!(RECORD_COUNT.load(Ordering::SeqCst), 1);
+}
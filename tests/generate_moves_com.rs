@@ -0,0 +1,37 @@
+#[allow(unused_imports)]
+use pretty_assertions::{assert_eq, assert_ne};
+
+use naitou_clone::*;
+
+/// COM 側の指し手生成順序が原作通りであることをテストする。
+///
+/// 思考ルーチンによる候補手の比較結果は生成順序に依存するので、順序が変わると COM の指し手も変わりうる。
+#[test]
+fn test_generate_moves_com_order() {
+    bbs::init();
+
+    // マスは９一, ８一, ..., １九の順に調べられる(玉 -> 香 -> 銀)。
+    // 各駒の移動先は遠隔利き、近接利きの順で、近接利きの方向順は駒種ごとに決まっている。
+    let pos = sfen_to_position("sfen 4k4/4l4/9/9/9/2s6/9/9/K8 w - 1");
+    let mvs = generate_moves_com(&pos);
+    assert_eq!(
+        sfen_encode_moves(&mvs),
+        "5a4b 5a6b 5a4a 5a6a \
+         5b5c 5b5d 5b5e 5b5f 5b5g+ 5b5h+ 5b5i+ \
+         7f6g+ 7f7g+ 7f8g+ 7f6e 7f8e"
+    );
+
+    // 駒打ちは空白マスごとに歩、香、桂、銀、金、角、飛車の順で生成される。
+    let pos = sfen_to_position("sfen 3k5/3g5/9/9/9/9/9/9/K8 w rgp 1");
+    let mvs = generate_moves_com(&pos);
+    assert_eq!(
+        sfen_encode_moves(&mvs[..8]),
+        "P*9a G*9a R*9a P*8a G*8a R*8a P*7a G*7a"
+    );
+}
+
+fn sfen_to_position(sfen: &str) -> Position {
+    let (side_to_move, board, hands) = sfen_decode_position(sfen).unwrap();
+
+    Position::new(side_to_move, board, hands)
+}
@@ -0,0 +1,58 @@
+use std::collections::HashSet;
+
+#[allow(unused_imports)]
+use pretty_assertions::{assert_eq, assert_ne};
+
+use naitou_clone::*;
+
+#[test]
+fn test_generate_moves_standard_prune_underpromotion() {
+    bbs::init();
+
+    let mut pos = sfen_to_position("sfen 4k4/9/9/9/9/9/9/R8/4K4 b - 1");
+
+    // 生成順は未規定なので、集合として比較する。
+    let mvs_of = |mvs: &MoveArray, src: Square, dst: Square| -> HashSet<Move> {
+        mvs.iter()
+            .copied()
+            .filter(|mv| !mv.is_drop() && mv.src() == src && mv.dst() == dst)
+            .collect()
+    };
+
+    // 既定では、飛車が敵陣に入る手は成と不成の両方が生成される。
+    let mvs = generate_moves_standard(&mut pos, GenOptions::default());
+    assert_eq!(mvs.len(), pos.legal_moves().len());
+    assert_eq!(
+        mvs_of(&mvs, SQ_98, SQ_93),
+        HashSet::from([
+            Move::new_walk_promotion(SQ_98, SQ_93),
+            Move::new_walk(SQ_98, SQ_93)
+        ])
+    );
+
+    // prune_underpromotion を指定すると、成る手のみが生成される。
+    let opts = GenOptions {
+        prune_underpromotion: true,
+    };
+    let mvs = generate_moves_standard(&mut pos, opts);
+    assert_eq!(
+        mvs_of(&mvs, SQ_98, SQ_93),
+        HashSet::from([Move::new_walk_promotion(SQ_98, SQ_93)])
+    );
+
+    // 敵陣に関係しない手や、成れない駒の手は影響を受けない。
+    assert_eq!(
+        mvs_of(&mvs, SQ_98, SQ_94),
+        HashSet::from([Move::new_walk(SQ_98, SQ_94)])
+    );
+    assert_eq!(
+        mvs_of(&mvs, SQ_59, SQ_48),
+        HashSet::from([Move::new_walk(SQ_59, SQ_48)])
+    );
+}
+
+fn sfen_to_position(sfen: &str) -> Position {
+    let (side_to_move, board, hands) = sfen_decode_position(sfen).unwrap();
+
+    Position::new(side_to_move, board, hands)
+}
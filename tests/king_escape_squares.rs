@@ -0,0 +1,41 @@
+#[allow(unused_imports)]
+use pretty_assertions::{assert_eq, assert_ne};
+
+use naitou_clone::*;
+
+/// 縦に王手をかけている飛車の利きが玉の背後まで抜けることを考慮しているかテストする。
+#[test]
+fn test_king_escape_squares_rook_check() {
+    bbs::init();
+
+    let pos = sfen_to_position("sfen 4r3k/9/9/9/4K4/9/9/9/9 b - 1");
+    assert!(pos.is_checked(HUM));
+
+    // ５六は現局面では飛車の利きがないが、玉が動けば利きが通る。
+    assert_eq!(pos.effect_count_board(COM)[SQ_56], 0);
+
+    let expected = [SQ_44, SQ_64, SQ_45, SQ_65, SQ_46, SQ_66]
+        .into_iter()
+        .fold(Bitboard::zero(), |acc, sq| acc | Bitboard::from(sq));
+    assert_eq!(king_escape_squares(&pos, HUM), expected);
+}
+
+/// 敵駒を取る手は、その駒に紐が付いていなければ可能であることをテストする。
+#[test]
+fn test_king_escape_squares_capture() {
+    bbs::init();
+
+    // ５八の金は５一の香で紐付いているので取れない。他のマスは全て金の利きがある。
+    let pos = sfen_to_position("sfen 4l3k/9/9/9/9/9/9/4g4/4K4 b - 1");
+    assert_eq!(king_escape_squares(&pos, HUM), Bitboard::zero());
+
+    // 香がなければ５八の金を取れる。
+    let pos = sfen_to_position("sfen 8k/9/9/9/9/9/9/4g4/4K4 b - 1");
+    assert_eq!(king_escape_squares(&pos, HUM), Bitboard::from(SQ_58));
+}
+
+fn sfen_to_position(sfen: &str) -> Position {
+    let (side_to_move, board, hands) = sfen_decode_position(sfen).unwrap();
+
+    Position::new(side_to_move, board, hands)
+}
@@ -179,6 +179,90 @@ fn test_perft_max_moves() {
     );
 }
 
+/// テストスイートの実行結果が正しく報告されるかテストする。
+#[test]
+fn test_perft_run_suite() {
+    const SUITE: &str = "\
+# 平手初期局面
+startpos ; D1 30 ; D2 900
+
+sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2 ; D1 30
+startpos moves 7g7f ; D1 31
+";
+
+    bbs::init();
+
+    let results = run_suite(SUITE.as_bytes()).unwrap();
+    assert_eq!(results.len(), 3);
+
+    assert_eq!(results[0].line, 2);
+    assert_eq!(results[0].sfen, "startpos");
+    assert!(results[0].passed());
+    assert_eq!(
+        results[0].depths,
+        [
+            SuiteDepthResult {
+                depth: 1,
+                expected: 30,
+                actual: 30,
+            },
+            SuiteDepthResult {
+                depth: 2,
+                expected: 900,
+                actual: 900,
+            },
+        ]
+    );
+
+    assert!(results[1].passed());
+
+    // 期待値が誤っている行は失敗として報告される。
+    assert_eq!(results[2].line, 5);
+    assert!(!results[2].passed());
+    assert_eq!(results[2].depths[0].expected, 31);
+    assert_eq!(results[2].depths[0].actual, 30);
+
+    // 構文エラーはエラーとなる。
+    assert!(run_suite("startpos ; D1".as_bytes()).is_err());
+    assert!(run_suite("startpos ; X1 30".as_bytes()).is_err());
+    assert!(run_suite("startpos".as_bytes()).is_err());
+
+    // 適用できない指し手を含む行はエラーとなり、行番号が報告される。
+    let err = run_suite("startpos ; D1 30\nstartpos moves 5e5d ; D1 30".as_bytes()).unwrap_err();
+    let msg = format!("{:#}", err);
+    assert!(msg.contains("line 2"), "{}", msg);
+    assert!(msg.contains("invalid move at index 0"), "{}", msg);
+}
+
+#[test]
+fn test_perft_with_progress() {
+    bbs::init();
+
+    for sfen in [
+        "startpos",
+        "sfen l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1",
+        // 打ち歩詰めの手はルートの指し手として通知されない。
+        "sfen 7nk/9/7G1/9/9/9/9/9/4K4 b P 1",
+    ] {
+        let mut pos = sfen_to_position(sfen);
+
+        for depth in 1..=2 {
+            let mut expected = 0;
+            perft(&mut pos, depth, |_| expected += 1);
+
+            let mut mvs = vec![];
+            let mut actual = 0;
+            perft_with_progress(&mut pos, depth, |mv, count| {
+                mvs.push(mv);
+                actual += count;
+            });
+
+            assert_eq!(actual, expected);
+            assert_eq!(mvs.len(), pos.legal_moves().len());
+        }
+    }
+}
+
 fn sfen_to_position(sfen: &str) -> Position {
     let (side_to_move, board, hands) = sfen_decode_position(sfen).unwrap();
 
@@ -4,7 +4,10 @@ use std::path::PathBuf;
 use pretty_assertions::{assert_eq, assert_ne};
 use walkdir::WalkDir;
 
-use naitou_clone::{sfen_decode, sfen_encode};
+use naitou_clone::{
+    sfen_decode, sfen_decode_moves, sfen_decode_position, sfen_encode, sfen_encode_moves, Handicap,
+    Position,
+};
 
 const SFEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/asset/sfen");
 
@@ -33,3 +36,99 @@ fn test_sfen_roundtrip() {
         assert_eq!(sfen, sfen_encoded);
     }
 }
+
+#[test]
+fn test_sfen_moves_roundtrip() {
+    const MOVES: &str = "7g7f 3c3d 8h2b+ 3a2b B*4e 2b3c 4e3d 3c3d P*3c";
+
+    let mvs = sfen_decode_moves(MOVES).unwrap();
+    assert_eq!(mvs.len(), 9);
+    assert!(mvs[2].is_promotion());
+    assert!(mvs[4].is_drop());
+    assert_eq!(sfen_encode_moves(&mvs), MOVES);
+
+    // 空白の扱い。
+    assert_eq!(sfen_decode_moves("  7g7f\t 3c3d  ").unwrap(), mvs[..2]);
+    assert!(sfen_decode_moves("").unwrap().is_empty());
+    assert_eq!(sfen_encode_moves([]), "");
+
+    assert!(sfen_decode_moves("7g7f 3c3x").is_err());
+}
+
+#[test]
+fn test_sfen_decode_position_command() {
+    const SFEN_STARTPOS: &str =
+        "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
+
+    let expected = sfen_decode(format!("{SFEN_STARTPOS} moves 7g7f")).unwrap();
+    assert_eq!(expected.3, sfen_decode_moves("7g7f").unwrap());
+
+    // "startpos" キーワードおよび先頭の "position" を受け付ける。
+    assert_eq!(sfen_decode("startpos moves 7g7f").unwrap(), expected);
+    assert_eq!(
+        sfen_decode("position startpos moves 7g7f").unwrap(),
+        expected
+    );
+
+    // 任意の空白を受け付ける。
+    assert_eq!(
+        sfen_decode(format!("\t position  {SFEN_STARTPOS}\tmoves   7g7f \n")).unwrap(),
+        expected
+    );
+    assert_eq!(
+        sfen_decode(
+            "position  sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL  w - 2 \t moves 3c3d  8h2b+"
+        )
+        .unwrap()
+        .3,
+        sfen_decode_moves("3c3d 8h2b+").unwrap()
+    );
+
+    // "moves" 以外のトークンや空の position コマンドは弾く。
+    assert!(sfen_decode("position").is_err());
+    assert!(sfen_decode("position startpos 7g7f").is_err());
+}
+
+#[test]
+fn test_position_to_sfen_hands() {
+    // 既存の USI エンジン/GUI が出力する sfen と一致すること。
+    const SFENS: &[&str] = &[
+        "startpos",
+        // USI プロトコル仕様書の例。
+        "sfen 8l/1l+R2P3/p2pBG1pp/kps1p4/Nn1P2G2/P1P1P2PP/1PS6/1KSG3+r1/LN2+p3L w Sbgn3p 1",
+        "sfen 4k4/9/9/9/9/9/9/9/4K4 b 2R2B4G4S4N4L17Pp 1",
+        "sfen lnsgkgsnl/1r7/pppppp1pp/6p2/9/2P6/PP1PPPPPP/7R1/LNSGKGSNL b Bb 1",
+    ];
+
+    for sfen in SFENS {
+        let (side_to_move, board, hands) = sfen_decode_position(sfen).unwrap();
+        let pos = Position::new(side_to_move, board, hands);
+        assert_eq!(pos.to_sfen(), *sfen);
+    }
+
+    // 入力の手駒の順番によらず、正規の順番で出力される。
+    let (side_to_move, board, hands) =
+        sfen_decode_position("sfen 4k4/9/9/9/9/9/9/9/4K4 b 3pPbRg 1").unwrap();
+    let pos = Position::new(side_to_move, board, hands);
+    assert_eq!(pos.to_sfen(), "sfen 4k4/9/9/9/9/9/9/9/4K4 b RPbg3p 1");
+}
+
+#[test]
+fn test_handicap_sfen() {
+    for handicap in Handicap::all() {
+        let (side_to_move, board, hands, mvs) = sfen_decode(handicap.sfen()).unwrap();
+        assert!(mvs.is_empty());
+
+        // 時間制限ありの平手は中飛車となる。
+        let timelimit = matches!(
+            handicap,
+            Handicap::HumSenteNakabisha | Handicap::ComSenteNakabisha
+        );
+        assert_eq!(
+            Handicap::from_startpos(side_to_move, &board, &hands, timelimit).unwrap(),
+            handicap
+        );
+    }
+
+    assert_eq!(Handicap::HumSenteSikenbisha.sfen(), "startpos");
+}